@@ -1,16 +1,27 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
-use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::draw::{DrawRecord, Seed};
+use crate::duplicates::find_existing;
+use crate::layout::{column_letter, Columns, MemberColumns, SheetLayout};
+use crate::selection::{Pick, RuleSet};
 
 use anyhow::{anyhow, Result};
-use google_sheets4::api::{ClearValuesRequest, ValueRange};
+use google_sheets4::api::{
+    BatchUpdateSpreadsheetRequest, ClearValuesRequest, DeleteDimensionRequest, DimensionRange,
+    InsertDimensionRequest, Request, ValueRange,
+};
 use google_sheets4::{hyper, hyper_rustls, oauth2, Sheets};
 use lazy_static::lazy_static;
 use rand::seq::SliceRandom;
-use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
 use serenity::async_trait;
 use tokio::sync::Mutex;
 
+/// How long a fetched list of tabs is trusted to be complete.
+const SHEET_IDS_TTL: Duration = Duration::from_secs(60);
+
 lazy_static! {
     static ref CREDS_JSON_PATH: String = {
         std::env::var("CREDS_JSON_PATH")
@@ -22,19 +33,50 @@ lazy_static! {
     };
 }
 
-const GET_ALBUMS_RANGE: &str = "Album Selection!A2:D";
-const GET_ROTATION_RANGE: &str = "Rotation!A1:A10";
-const GET_NAMES: &str = "Rotation!B1:B10";
-const GET_LAST_GENRE_RANGE: &str = "Ratings!C2:D2";
-const GET_CURRENT_RANGE: &str = "Ratings!A2:D2";
-
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Album {
     pub name: String,
     pub artist: String,
     pub genre: String,
     pub added_by: String,
+    /// Where the album lives in its backend: the row index in the backlog tab for
+    /// the sheet, the primary key for SQLite.
     pub row: usize,
+    /// When the album was picked, if it has been. Played albums stay in the
+    /// backlog but are never drawn again.
+    pub played_on: Option<String>,
+    /// The release year and running time in minutes, for clubs whose rules
+    /// care about them.
+    pub year: Option<u16>,
+    pub length: Option<u32>,
+    /// When the album went into the backlog, if anyone wrote it down. Albums that
+    /// have waited longer are more likely to be drawn.
+    pub added_on: Option<String>,
+    /// What the submitter said to sell the album to the club.
+    pub pitch: Option<String>,
+    /// The album on Spotify, as confirmed by the submitter.
+    pub spotify_id: Option<String>,
+    /// The submitter's Discord ID, for albums submitted through the bot. Only
+    /// they can edit or withdraw the album; `added_by` is just for showing.
+    pub submitter_id: Option<String>,
+}
+
+/// A row of the ratings history: the album that was played, the date it was
+/// picked, and whatever the club recorded about it (scores, notes), kept verbatim.
+#[derive(Clone, Debug)]
+pub struct Rating {
+    pub album: Album,
+    pub date: Option<String>,
+    pub extra: Vec<String>,
+}
+
+/// Someone in the club. Only active members count towards a full rotation or get
+/// drawn as reviewers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Member {
+    pub name: String,
+    pub active: bool,
+    pub joined: Option<String>,
 }
 
 impl Display for Album {
@@ -47,18 +89,261 @@ impl Display for Album {
     }
 }
 
+pub(crate) fn album_from_vec(values: &[String], row: usize) -> Result<Album> {
+    if values.is_empty() {
+        Err(anyhow!("No albums found"))
+    } else {
+        let artist = values
+            .first()
+            .ok_or_else(|| anyhow!("Unable to get album artist"))?
+            .to_owned();
+
+        let name = values
+            .get(1)
+            .ok_or_else(|| anyhow!("Unable to get album name"))?
+            .to_owned();
+
+        let genre = values
+            .get(2)
+            .ok_or_else(|| anyhow!("Unable to get album genre"))?
+            .to_owned();
+
+        let added_by = values
+            .get(3)
+            .ok_or_else(|| anyhow!("Unable to get album added_by"))?
+            .to_owned();
+
+        Ok(Album {
+            name,
+            artist,
+            genre,
+            added_by,
+            row,
+            ..Default::default()
+        })
+    }
+}
+
+/// Whether two rows hold the same album, wherever they came from.
+pub(crate) fn same_album(a: &Album, b: &Album) -> bool {
+    a.artist == b.artist && a.name == b.name && a.added_by == b.added_by
+}
+
+/// Trims a submission's required fields, checking them the same way as rows read
+/// from the sheet.
+fn check_submission(album: &Album) -> Result<Album> {
+    // Stops at the first blank field so album_from_vec can say which one it was
+    let values: Vec<String> = [&album.artist, &album.name, &album.genre, &album.added_by]
+        .iter()
+        .map(|value| value.trim().to_owned())
+        .take_while(|value| !value.is_empty())
+        .collect();
+    let checked = album_from_vec(&values, album.row)?;
+    Ok(Album {
+        artist: checked.artist,
+        name: checked.name,
+        genre: checked.genre,
+        added_by: checked.added_by,
+        ..album.clone()
+    })
+}
+
+/// Whose nominations to look at: a member's own, found by their Discord ID, or
+/// anyone's by the name they were submitted under. Names can be copied, so only
+/// admins get to go by name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Submitter {
+    Id(String),
+    Name(String),
+}
+
+impl Submitter {
+    fn submitted(&self, album: &Album) -> bool {
+        match self {
+            Submitter::Id(id) => album.submitter_id.as_ref() == Some(id),
+            Submitter::Name(name) => album.added_by == *name,
+        }
+    }
+}
+
+impl Display for Submitter {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Submitter::Id(id) => write!(f, "<@{}>", id),
+            Submitter::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Picks the album on `row` out of someone's submissions.
+fn find_submission(submissions: Vec<Album>, submitter: &Submitter, row: usize) -> Result<Album> {
+    submissions
+        .into_iter()
+        .find(|album| album.row == row)
+        .ok_or_else(|| {
+            anyhow!(
+                "That album isn't one of {}'s waiting in the backlog",
+                submitter
+            )
+        })
+}
+
+pub(crate) fn today() -> String {
+    chrono::Local::now().format("%Y-%m-%d").to_string()
+}
+
 #[async_trait]
 pub trait AlbumRepo {
-    async fn fetch_random_album(&self) -> Result<Album>;
     async fn get_current(&self) -> Result<Album>;
     async fn get_random_name(&self) -> Result<String>;
     async fn reset_reviewers(&self) -> Result<()>;
-    async fn add_name_to_rotation(&self, name: String) -> Result<()>;
+    /// Adds `name` to the rotation, clearing it once everyone is in. Returns
+    /// whether it was cleared.
+    async fn add_name_to_rotation(&self, name: String) -> Result<bool>;
+    /// Flags a picked album as played so it is never drawn again, on
+    /// `album.played_on` if that is set and today otherwise.
+    async fn mark_played(&self, album: &Album) -> Result<()>;
+    /// Puts a played album back in the draw.
+    async fn unmark_played(&self, album: &Album) -> Result<()>;
+    /// Takes `album` back off the top of the ratings history, as long as it is
+    /// still the current album.
+    async fn remove_current(&self, album: &Album) -> Result<()>;
+
+    /// Every album in the backlog.
+    async fn list_albums(&self) -> Result<Vec<Album>>;
+    /// Everyone in the club, active or not.
+    async fn list_members(&self) -> Result<Vec<Member>>;
+    /// The names in the current rotation, in the order they were added.
+    async fn list_rotation(&self) -> Result<Vec<String>>;
+    /// The ratings history, newest (the current album) first.
+    async fn list_ratings(&self) -> Result<Vec<Rating>>;
+    async fn add_albums(&self, albums: &[Album]) -> Result<()>;
+    /// Overwrites `album` in the backlog with `edited`.
+    async fn update_album(&self, album: &Album, edited: &Album) -> Result<()>;
+    /// Deletes `album` from the backlog.
+    async fn remove_album(&self, album: &Album) -> Result<()>;
+    async fn add_members(&self, members: &[Member]) -> Result<()>;
+    /// Replaces the rotation wholesale, without the clear-when-full rule.
+    async fn set_rotation(&self, names: &[String]) -> Result<()>;
+    /// Records ratings on top of the history. `ratings` is newest first, like
+    /// `list_ratings`, so the first one becomes the current album.
+    async fn add_ratings(&self, ratings: &[Rating]) -> Result<()>;
+    /// Keeps a record of a committed draw so it can be verified later.
+    async fn add_draw(&self, draw: &DrawRecord) -> Result<()>;
+    /// Every recorded draw, oldest first.
+    async fn list_draws(&self) -> Result<Vec<DrawRecord>>;
+    /// Reads something the bot keeps between restarts.
+    async fn get_state(&self, key: &str) -> Result<Option<String>>;
+    /// Stores something the bot keeps between restarts, or forgets it on `None`.
+    async fn set_state(&self, key: &str, value: Option<&str>) -> Result<()>;
+    /// Where `album` is kept, for pointing at it by hand.
+    fn locate(&self, album: &Album) -> String;
+
+    /// Checks that the backend has everything the bot writes to, so a missing
+    /// piece shows up at startup rather than on the first commit.
+    async fn check(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Draws an album from the backlog under `rules`, using `seed`.
+    async fn fetch_random_album(&self, rules: &RuleSet, seed: &Seed) -> Result<Pick> {
+        rules.draw(self, seed).await
+    }
+
+    /// Adds a member's submission to the end of the backlog, dated today, and
+    /// returns it as added. The required fields are checked the same way as
+    /// rows read from the sheet, and albums already in the backlog or played
+    /// are turned away.
+    async fn submit_album(&self, album: &Album) -> Result<Album> {
+        let submitted = Album {
+            played_on: None,
+            added_on: Some(today()),
+            ..check_submission(album)?
+        };
+        let backlog = self.list_albums().await?;
+        let backlog: Vec<&Album> = backlog.iter().collect();
+        let ratings = self.list_ratings().await?;
+        if let Some(existing) =
+            find_existing(&submitted, &backlog, &ratings, &|album| self.locate(album))
+        {
+            return Err(anyhow!("{}", existing));
+        }
+        self.add_albums(std::slice::from_ref(&submitted)).await?;
+        Ok(submitted)
+    }
+
+    /// The albums `submitter` submitted that are still waiting to be drawn.
+    async fn list_submissions(&self, submitter: &Submitter) -> Result<Vec<Album>> {
+        Ok(self
+            .list_albums()
+            .await?
+            .into_iter()
+            .filter(|album| submitter.submitted(album) && album.played_on.is_none())
+            .collect())
+    }
+
+    /// Replaces the artist, name, genre and pitch of one of `submitter`'s waiting
+    /// albums, checked like a new submission, and returns it as saved. A new
+    /// artist or name drops the Spotify match, which may no longer be right.
+    async fn edit_submission(
+        &self,
+        submitter: &Submitter,
+        row: usize,
+        edited: &Album,
+    ) -> Result<Album> {
+        let album = find_submission(self.list_submissions(submitter).await?, submitter, row)?;
+        let checked = check_submission(&Album {
+            added_by: album.added_by.clone(),
+            ..edited.clone()
+        })?;
+        let same_record = checked.artist == album.artist && checked.name == album.name;
+        let saved = Album {
+            artist: checked.artist,
+            name: checked.name,
+            genre: checked.genre,
+            pitch: checked.pitch,
+            spotify_id: album.spotify_id.clone().filter(|_| same_record),
+            ..album.clone()
+        };
+        let backlog = self.list_albums().await?;
+        let others: Vec<&Album> = backlog.iter().filter(|other| other.row != row).collect();
+        let ratings = self.list_ratings().await?;
+        if let Some(existing) =
+            find_existing(&saved, &others, &ratings, &|album| self.locate(album))
+        {
+            return Err(anyhow!("{}", existing));
+        }
+        self.update_album(&album, &saved).await?;
+        Ok(saved)
+    }
+
+    /// Takes one of `submitter`'s waiting albums out of the backlog and returns it.
+    async fn withdraw_submission(&self, submitter: &Submitter, row: usize) -> Result<Album> {
+        let album = find_submission(self.list_submissions(submitter).await?, submitter, row)?;
+        self.remove_album(&album).await?;
+        Ok(album)
+    }
+
+    /// Makes a freshly picked album the current one by putting it on top of the
+    /// ratings history, dated today.
+    async fn set_current(&self, album: &Album) -> Result<()> {
+        let rating = Rating {
+            album: album.clone(),
+            date: Some(today()),
+            extra: Vec::new(),
+        };
+        self.add_ratings(&[rating]).await
+    }
 }
 
 pub struct GoogleSheetsAlbumRepo {
     hub: Sheets,
-    persons: Arc<Mutex<Vec<String>>>,
+    layout: SheetLayout,
+    /// Held while the reviewer queue is read and written back.
+    reviewers: Mutex<()>,
+    /// The ID of each tab by title, and when they were last fetched. They're
+    /// fetched again when a tab isn't found, at most once a minute.
+    sheet_ids: Mutex<(HashMap<String, i32>, Option<Instant>)>,
 }
 
 impl GoogleSheetsAlbumRepo {
@@ -79,98 +364,306 @@ impl GoogleSheetsAlbumRepo {
             ),
             auth,
         );
-        return Ok(GoogleSheetsAlbumRepo {
+        Ok(GoogleSheetsAlbumRepo {
             hub,
-            persons: Arc::new(Mutex::new(Vec::new())),
-        });
-    }
-
-    async fn album_from_vec(&self, values: &Vec<String>, row: usize) -> Result<Album> {
-        if values.is_empty() {
-            Err(anyhow!("No albums found"))
-        } else {
-            let artist = values
-                .get(0)
-                .ok_or_else(|| anyhow!("Unable to get album artist"))?
-                .to_owned();
-
-            let name = values
-                .get(1)
-                .ok_or_else(|| anyhow!("Unable to get album name"))?
-                .to_owned();
-
-            let genre = values
-                .get(2)
-                .ok_or_else(|| anyhow!("Unable to get album genre"))?
-                .to_owned();
-
-            let added_by = values
-                .get(3)
-                .ok_or_else(|| anyhow!("Unable to get album added_by"))?
-                .to_owned();
-
-            Ok(Album {
-                name,
-                artist,
-                genre,
-                added_by,
-                row,
-            })
-        }
+            layout: SheetLayout::from_env()?,
+            reviewers: Mutex::new(()),
+            sheet_ids: Mutex::new((HashMap::new(), None)),
+        })
     }
 
-    async fn get_last_genre_and_added_by(&self) -> Result<(String, String)> {
+    async fn get_values(&self, range: &str) -> Result<Vec<Vec<String>>> {
         let (_, spreadsheet) = self
             .hub
             .spreadsheets()
-            .values_get(&DOC_ID, GET_LAST_GENRE_RANGE)
+            .values_get(&DOC_ID, range)
             .doit()
             .await?;
-        let row: Vec<String> = spreadsheet
-            .values
-            .as_ref()
-            .ok_or_else(|| anyhow!("Unable to get last genre"))?
-            .iter()
+        Ok(spreadsheet.values.unwrap_or_default())
+    }
+
+    async fn get_column_strings(&self, range: &str) -> Result<Vec<String>> {
+        Ok(self
+            .get_values(range)
+            .await?
+            .into_iter()
             .flatten()
-            .map(|x| x.to_owned())
-            .collect::<Vec<String>>();
-        let genre = row
-            .get(0)
-            .ok_or_else(|| anyhow!("Error getting last genre"))?;
-        let selected_by = row
-            .get(1)
-            .ok_or_else(|| anyhow!("Error getting last genre"))?;
-        Ok((genre.to_owned(), selected_by.to_owned()))
+            .filter(|cell| !cell.is_empty())
+            .collect())
     }
 
     async fn get_column_strings_as_hashset(&self, range: &str) -> Result<HashSet<String>> {
-        let (_, spreadsheet) = self
-            .hub
+        Ok(HashSet::from_iter(self.get_column_strings(range).await?))
+    }
+
+    /// Reads a whole backlog or ratings tab, returning its columns and data rows.
+    async fn get_tab(&self, tab: &str) -> Result<(Columns, Vec<Vec<String>>)> {
+        let mut values = self.get_values(tab).await?;
+        let (columns, _) = self.layout.split_tab(tab, &values)?;
+        values.remove(0);
+        Ok((columns, values))
+    }
+
+    async fn get_tab_columns(&self, tab: &str) -> Result<Columns> {
+        let values = self.get_values(&format!("{}!1:1", tab)).await?;
+        let (columns, _) = self.layout.split_tab(tab, &values)?;
+        Ok(columns)
+    }
+
+    async fn append_rows(&self, range: &str, rows: Vec<Vec<String>>) -> Result<()> {
+        let value_range = ValueRange {
+            major_dimension: Some("ROWS".to_string()),
+            range: Some(range.to_string()),
+            values: Some(rows),
+        };
+        self.hub
             .spreadsheets()
-            .values_get(&DOC_ID, range)
+            .values_append(value_range, &DOC_ID, range)
+            .value_input_option("RAW")
+            .doit()
+            .await?;
+        Ok(())
+    }
+
+    /// Finds the backlog row holding `album`. People edit the sheet by hand, so
+    /// the row the album was read from is only trusted if it still holds it.
+    async fn find_album_row(&self, album: &Album) -> Result<usize> {
+        let albums = self.list_albums().await?;
+        let same = |other: &&Album| same_album(other, album);
+        albums
+            .iter()
+            .find(|other| other.row == album.row && same(other))
+            .or_else(|| albums.iter().find(same))
+            .map(|other| other.row)
+            .ok_or_else(|| anyhow!("{} is no longer in the backlog", album))
+    }
+
+    /// The ID of the tab called `title`, or `None` if the spreadsheet has no such
+    /// tab. Failing to reach the spreadsheet is an error, not a missing tab.
+    async fn find_sheet_id(&self, title: &str) -> Result<Option<i32>> {
+        let mut cache = self.sheet_ids.lock().await;
+        let (sheet_ids, fetched) = &mut *cache;
+        let fresh = fetched.is_some_and(|at| at.elapsed() < SHEET_IDS_TTL);
+        if sheet_ids.contains_key(title) || fresh {
+            return Ok(sheet_ids.get(title).copied());
+        }
+        let (_, spreadsheet) = self.hub.spreadsheets().get(&DOC_ID).doit().await?;
+        *sheet_ids = spreadsheet
+            .sheets
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|sheet| sheet.properties)
+            .filter_map(|properties| Some((properties.title?, properties.sheet_id?)))
+            .collect();
+        *fetched = Some(Instant::now());
+        Ok(sheet_ids.get(title).copied())
+    }
+
+    async fn get_sheet_id(&self, title: &str) -> Result<i32> {
+        self.find_sheet_id(title)
+            .await?
+            .ok_or_else(|| anyhow!("Unable to find the {} tab", title))
+    }
+
+    /// The backlog's Played column, which picks are marked in so they aren't
+    /// drawn again.
+    async fn played_column(&self) -> Result<usize> {
+        let tab = &self.layout.albums_tab;
+        self.get_tab_columns(tab).await?.played.ok_or_else(|| {
+            anyhow!(
+                "The {} tab has no {} column, which picked albums are marked in. Add one to \
+                its header row.",
+                tab,
+                self.layout.headers.played
+            )
+        })
+    }
+
+    async fn write_played(&self, album: &Album, played_on: String) -> Result<()> {
+        let tab = &self.layout.albums_tab;
+        let played = self.played_column().await?;
+        let row = self.find_album_row(album).await?;
+        let range = format!("{}!{}{}", tab, column_letter(played), row + 2);
+        self.write_rows(&range, vec![vec![played_on]]).await
+    }
+
+    /// Deletes rows `start` up to `end` of `title`, counting the header as 0.
+    async fn delete_rows(&self, title: &str, start: i32, end: i32) -> Result<()> {
+        let sheet_id = self.get_sheet_id(title).await?;
+        let delete = Request {
+            delete_dimension: Some(DeleteDimensionRequest {
+                range: Some(DimensionRange {
+                    dimension: Some("ROWS".to_string()),
+                    sheet_id: Some(sheet_id),
+                    start_index: Some(start),
+                    end_index: Some(end),
+                }),
+            }),
+            ..Default::default()
+        };
+        let req = BatchUpdateSpreadsheetRequest {
+            requests: Some(vec![delete]),
+            ..Default::default()
+        };
+        self.hub
+            .spreadsheets()
+            .batch_update(req, &DOC_ID)
+            .doit()
+            .await?;
+        Ok(())
+    }
+
+    /// Inserts `rows` directly under the header row of `title`, pushing everything
+    /// else down.
+    async fn insert_rows_at_top(&self, title: &str, rows: Vec<Vec<String>>) -> Result<()> {
+        let sheet_id = self.get_sheet_id(title).await?;
+        let insert = Request {
+            insert_dimension: Some(InsertDimensionRequest {
+                inherit_from_before: Some(false),
+                range: Some(DimensionRange {
+                    dimension: Some("ROWS".to_string()),
+                    sheet_id: Some(sheet_id),
+                    start_index: Some(1),
+                    end_index: Some(1 + rows.len() as i32),
+                }),
+            }),
+            ..Default::default()
+        };
+        let req = BatchUpdateSpreadsheetRequest {
+            requests: Some(vec![insert]),
+            ..Default::default()
+        };
+        self.hub
+            .spreadsheets()
+            .batch_update(req, &DOC_ID)
+            .doit()
+            .await?;
+        let range = format!("{}!A2", title);
+        let value_range = ValueRange {
+            major_dimension: Some("ROWS".to_string()),
+            range: Some(range.clone()),
+            values: Some(rows),
+        };
+        self.hub
+            .spreadsheets()
+            .values_update(value_range, &DOC_ID, &range)
+            .value_input_option("RAW")
+            .doit()
+            .await?;
+        Ok(())
+    }
+
+    async fn write_rows(&self, range: &str, rows: Vec<Vec<String>>) -> Result<()> {
+        let value_range = ValueRange {
+            major_dimension: Some("ROWS".to_string()),
+            range: Some(range.to_string()),
+            values: Some(rows),
+        };
+        self.hub
+            .spreadsheets()
+            .values_update(value_range, &DOC_ID, range)
+            .value_input_option("RAW")
+            .doit()
+            .await?;
+        Ok(())
+    }
+
+    /// This cycle's reviewer queue: each name, and the date they reviewed if
+    /// they have. Blank rows are kept so indexes line up with the sheet.
+    async fn get_reviewers(&self) -> Result<Vec<(String, Option<String>)>> {
+        let range = format!("{}!A2:B", self.layout.reviewers_tab);
+        Ok(self
+            .get_values(&range)
+            .await?
+            .into_iter()
+            .map(|row| {
+                let name = row.first().cloned().unwrap_or_default();
+                let reviewed = row.get(1).filter(|date| !date.is_empty()).cloned();
+                (name, reviewed)
+            })
+            .collect())
+    }
+
+    /// Starts a new reviewer cycle with every active member except whoever
+    /// submitted the current album.
+    async fn start_reviewer_cycle(&self) -> Result<Vec<(String, Option<String>)>> {
+        let current_album = self.get_current().await?;
+        let mut names: Vec<String> = self
+            .get_names()
+            .await?
+            .into_iter()
+            .filter(|name| name != &current_album.added_by)
+            .collect();
+        names.sort();
+        let range = format!("{}!A2:B", self.layout.reviewers_tab);
+        self.hub
+            .spreadsheets()
+            .values_clear(ClearValuesRequest::default(), &DOC_ID, &range)
             .doit()
             .await?;
-        let names: HashSet<String> = HashSet::from_iter(
-            spreadsheet
-                .values
-                .ok_or("Error getting rotation")
+        if !names.is_empty() {
+            let rows = names.iter().map(|name| vec![name.clone(), String::new()]);
+            self.write_rows(&range, rows.collect()).await?;
+        }
+        Ok(names.into_iter().map(|name| (name, None)).collect())
+    }
+
+    /// The key and value rows of the state tab, under its header row.
+    async fn get_state_rows(&self) -> Result<Vec<Vec<String>>> {
+        let mut rows = self
+            .get_values(&format!("{}!A:B", self.layout.state_tab))
+            .await?;
+        if !rows.is_empty() {
+            rows.remove(0);
+        }
+        Ok(rows)
+    }
+
+    /// Reads the members tab, or the legacy names column if there isn't one.
+    async fn get_members(&self) -> Result<(Option<MemberColumns>, Vec<Member>)> {
+        let tab = &self.layout.members_tab;
+        if self.find_sheet_id(tab).await?.is_none() {
+            let members = self
+                .get_column_strings(&self.layout.names_range())
+                .await?
                 .into_iter()
-                .flatten()
-                .flatten(),
-        );
-        Ok(names)
+                .map(|name| Member {
+                    name,
+                    active: true,
+                    joined: None,
+                })
+                .collect();
+            return Ok((None, members));
+        }
+        let values = self.get_values(tab).await?;
+        let (header, rows) = values
+            .split_first()
+            .ok_or_else(|| anyhow!("The {} tab has no header row", tab))?;
+        let columns = self.layout.member_columns(header)?;
+        let members = columns.members(rows);
+        Ok((Some(columns), members))
     }
 
     async fn get_names(&self) -> Result<HashSet<String>> {
-        self.get_column_strings_as_hashset(GET_NAMES).await
+        let (_, members) = self.get_members().await?;
+        Ok(members
+            .into_iter()
+            .filter(|member| member.active)
+            .map(|member| member.name)
+            .collect())
     }
 
     async fn get_rotation(&self) -> Result<HashSet<String>> {
-        self.get_column_strings_as_hashset(GET_ROTATION_RANGE).await
+        self.get_column_strings_as_hashset(&self.layout.rotation_range())
+            .await
     }
 
     async fn is_full_rotation(&self, rotation: HashSet<String>) -> Result<bool> {
         let names = self.get_names().await?;
+        // Nobody to go round means the names couldn't be read, not a full rotation
+        if names.is_empty() {
+            return Ok(false);
+        }
         for name in names {
             if !rotation.contains(&name) {
                 return Ok(false);
@@ -183,119 +676,238 @@ impl GoogleSheetsAlbumRepo {
         let req = ClearValuesRequest::default();
         self.hub
             .spreadsheets()
-            .values_clear(req, &DOC_ID, GET_ROTATION_RANGE)
+            .values_clear(req, &DOC_ID, &self.layout.rotation_range())
             .doit()
             .await?;
         Ok(())
     }
-
-    async fn select_random_album(
-        &self,
-        spreadsheet: &[Vec<String>],
-        rotation: &HashSet<String>,
-        last_genre: &str,
-        last_added_by: &str,
-    ) -> Result<Album> {
-        let mut i = 0;
-        let mut filtered_albums = Vec::new();
-        for x in spreadsheet {
-            let album = self.album_from_vec(x, i).await?;
-            i += 1;
-            if !rotation.contains(&album.added_by)
-                && &album.added_by.to_lowercase() != &last_added_by.to_lowercase()
-                && &album.genre.to_lowercase() != &last_genre.to_lowercase()
-            {
-                filtered_albums.push(album)
-            }
-        }
-        let row_count = filtered_albums.len();
-        let num = rand::thread_rng().gen_range(0..row_count);
-        Ok(filtered_albums[num].to_owned())
-    }
 }
 
 #[async_trait]
 impl AlbumRepo for GoogleSheetsAlbumRepo {
-    async fn add_name_to_rotation(&self, name: String) -> Result<()> {
+    async fn add_name_to_rotation(&self, name: String) -> Result<bool> {
+        let range = self.layout.rotation_range();
         let value_range = ValueRange {
             major_dimension: Some("COLUMNS".to_string()),
-            range: Some(GET_ROTATION_RANGE.to_string()),
+            range: Some(range.clone()),
             values: Some(vec![vec![name.to_owned()]]),
         };
         self.hub
             .spreadsheets()
-            .values_append(value_range, &DOC_ID, GET_ROTATION_RANGE)
+            .values_append(value_range, &DOC_ID, &range)
             .value_input_option("RAW")
             .doit()
             .await?;
         let mut rotation = self.get_rotation().await?;
         rotation.insert(name);
-        if self.is_full_rotation(rotation).await? {
+        let full = self.is_full_rotation(rotation).await?;
+        if full {
             self.clear_rotation().await?;
         }
-        Ok(())
+        Ok(full)
     }
 
     async fn reset_reviewers(&self) -> Result<()> {
-        let mut lock = self.persons.lock().await;
-        let current_album = self.get_current().await?;
-        *lock = self
-            .get_names()
-            .await?
-            .into_iter()
-            .filter(|name| name != &current_album.added_by)
-            .collect();
+        let _lock = self.reviewers.lock().await;
+        self.start_reviewer_cycle().await?;
         Ok(())
     }
 
     async fn get_random_name(&self) -> Result<String> {
-        let mut lock = self.persons.lock().await;
-        if lock.len() == 0 {
-            let current_album = self.get_current().await?;
-            *lock = self
-                .get_names()
-                .await?
-                .into_iter()
-                .filter(|name| name != &current_album.added_by)
-                .collect();
-            lock.shuffle(&mut rand::thread_rng());
+        let _lock = self.reviewers.lock().await;
+        let up =
+            |(name, reviewed): &(String, Option<String>)| !name.is_empty() && reviewed.is_none();
+        let mut reviewers = self.get_reviewers().await?;
+        if !reviewers.iter().any(up) {
+            reviewers = self.start_reviewer_cycle().await?;
         }
-        Ok(lock.remove(0))
+        let remaining: Vec<usize> = (0..reviewers.len())
+            .filter(|&i| up(&reviewers[i]))
+            .collect();
+        let &i = remaining
+            .choose(&mut rand::thread_rng())
+            .ok_or_else(|| anyhow!("No reviewers left to pick from"))?;
+        let range = format!("{}!B{}", self.layout.reviewers_tab, i + 2);
+        self.write_rows(&range, vec![vec![today()]]).await?;
+        Ok(reviewers.swap_remove(i).0)
     }
+
     async fn get_current(&self) -> Result<Album> {
-        let (_, spreadsheet) = self
-            .hub
+        let tab = &self.layout.ratings_tab;
+        let values = self.get_values(&self.layout.current_range()).await?;
+        let (columns, rows) = self.layout.split_tab(tab, &values)?;
+        let rating = columns
+            .ratings(rows)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Unable to get current album"))?;
+        Ok(rating.album)
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.played_column().await.map(|_| ())
+    }
+
+    async fn mark_played(&self, album: &Album) -> Result<()> {
+        let played_on = album.played_on.clone().unwrap_or_else(today);
+        self.write_played(album, played_on).await
+    }
+
+    async fn unmark_played(&self, album: &Album) -> Result<()> {
+        self.write_played(album, String::new()).await
+    }
+
+    async fn remove_current(&self, album: &Album) -> Result<()> {
+        if !same_album(&self.get_current().await?, album) {
+            return Err(anyhow!("{} is no longer the current album", album));
+        }
+        self.delete_rows(&self.layout.ratings_tab, 1, 2).await
+    }
+
+    async fn list_albums(&self) -> Result<Vec<Album>> {
+        let (columns, rows) = self.get_tab(&self.layout.albums_tab).await?;
+        columns.albums(&rows)
+    }
+
+    async fn list_members(&self) -> Result<Vec<Member>> {
+        Ok(self.get_members().await?.1)
+    }
+
+    async fn list_rotation(&self) -> Result<Vec<String>> {
+        self.get_column_strings(&self.layout.rotation_range()).await
+    }
+
+    async fn list_ratings(&self) -> Result<Vec<Rating>> {
+        let (columns, rows) = self.get_tab(&self.layout.ratings_tab).await?;
+        columns.ratings(&rows)
+    }
+
+    async fn add_albums(&self, albums: &[Album]) -> Result<()> {
+        let tab = &self.layout.albums_tab;
+        let columns = self.get_tab_columns(tab).await?;
+        let rows = albums.iter().map(|album| columns.backlog_row(album));
+        self.append_rows(tab, rows.collect()).await
+    }
+
+    async fn update_album(&self, album: &Album, edited: &Album) -> Result<()> {
+        let tab = &self.layout.albums_tab;
+        let columns = self.get_tab_columns(tab).await?;
+        let row = self.find_album_row(album).await?;
+        let range = format!("{}!A{}", tab, row + 2);
+        let existing = self.get_values(&range).await?.pop().unwrap_or_default();
+        let values = columns.edited_row(&existing, edited);
+        self.write_rows(&range, vec![values]).await
+    }
+
+    async fn remove_album(&self, album: &Album) -> Result<()> {
+        let row = self.find_album_row(album).await? as i32;
+        self.delete_rows(&self.layout.albums_tab, row + 1, row + 2)
+            .await
+    }
+
+    async fn add_members(&self, members: &[Member]) -> Result<()> {
+        match self.get_members().await?.0 {
+            Some(columns) => {
+                let rows = members.iter().map(|member| columns.member_row(member));
+                self.append_rows(&self.layout.members_tab, rows.collect())
+                    .await
+            }
+            None => {
+                let rows = members.iter().map(|member| vec![member.name.clone()]);
+                self.append_rows(&self.layout.names_range(), rows.collect())
+                    .await
+            }
+        }
+    }
+
+    async fn set_rotation(&self, names: &[String]) -> Result<()> {
+        self.clear_rotation().await?;
+        if names.is_empty() {
+            return Ok(());
+        }
+        let range = self.layout.rotation_range();
+        let value_range = ValueRange {
+            major_dimension: Some("COLUMNS".to_string()),
+            range: Some(range.clone()),
+            values: Some(vec![names.to_vec()]),
+        };
+        self.hub
             .spreadsheets()
-            .values_get(&DOC_ID, GET_CURRENT_RANGE)
+            .values_update(value_range, &DOC_ID, &range)
+            .value_input_option("RAW")
             .doit()
             .await?;
-        let row: Vec<String> = spreadsheet
-            .values
-            .as_ref()
-            .ok_or_else(|| anyhow!("Unable to get current album"))?
+        Ok(())
+    }
+
+    async fn add_ratings(&self, ratings: &[Rating]) -> Result<()> {
+        if ratings.is_empty() {
+            return Ok(());
+        }
+        let tab = &self.layout.ratings_tab;
+        let columns = self.get_tab_columns(tab).await?;
+        let rows = ratings.iter().map(|rating| columns.rating_row(rating));
+        self.insert_rows_at_top(tab, rows.collect()).await
+    }
+
+    async fn add_draw(&self, draw: &DrawRecord) -> Result<()> {
+        let pick = draw.pick()?;
+        let row = vec![
+            draw.date.clone(),
+            format!("{} by {}", pick.album, pick.artist),
+            draw.seed_hash.clone(),
+            draw.seed.clone(),
+            serde_json::to_string(draw)?,
+        ];
+        self.append_rows(&self.layout.draws_tab, vec![row]).await
+    }
+
+    async fn list_draws(&self) -> Result<Vec<DrawRecord>> {
+        // Under the header row: date, album, seed hash, seed and the full record
+        self.get_values(&self.layout.draws_tab)
+            .await?
             .iter()
-            .flatten()
-            .map(|x| x.to_owned())
-            .collect::<Vec<String>>();
-        self.album_from_vec(&row, 0).await
+            .skip(1)
+            .filter_map(|row| row.get(4).filter(|record| !record.is_empty()))
+            .map(|record| Ok(serde_json::from_str(record)?))
+            .collect()
     }
 
-    async fn fetch_random_album(&self) -> Result<Album> {
-        let (_, spreadsheet) = self
-            .hub
-            .spreadsheets()
-            .values_get(&DOC_ID, GET_ALBUMS_RANGE)
-            .doit()
-            .await?;
-        let albums = &spreadsheet
-            .values
-            .ok_or_else(|| anyhow!("Error fetching albums"))?;
-        let rotation = self.get_rotation().await?;
-        let (last_genre, last_added_by) = self.get_last_genre_and_added_by().await?;
-        let album = self
-            .select_random_album(albums, &rotation, &last_genre, &last_added_by)
-            .await?;
-        Ok(album)
+    async fn get_state(&self, key: &str) -> Result<Option<String>> {
+        Ok(self
+            .get_state_rows()
+            .await?
+            .into_iter()
+            .find(|row| row.first().map(String::as_str) == Some(key))
+            .and_then(|row| row.get(1).cloned())
+            .filter(|value| !value.is_empty()))
+    }
+
+    async fn set_state(&self, key: &str, value: Option<&str>) -> Result<()> {
+        let tab = &self.layout.state_tab;
+        let value = value.unwrap_or_default().to_owned();
+        let rows = self.get_state_rows().await?;
+        match rows
+            .iter()
+            .position(|row| row.first().map(String::as_str) == Some(key))
+        {
+            Some(i) => {
+                let range = format!("{}!B{}", tab, i + 2);
+                self.write_rows(&range, vec![vec![value]]).await
+            }
+            None => {
+                self.append_rows(tab, vec![vec![key.to_owned(), value]])
+                    .await
+            }
+        }
+    }
+
+    fn locate(&self, album: &Album) -> String {
+        format!(
+            "row {} of the {} tab",
+            album.row + 2,
+            self.layout.albums_tab
+        )
     }
 }
 
@@ -309,7 +921,7 @@ mod test {
         env_logger::init();
         let repo = GoogleSheetsAlbumRepo::default().await?;
 
-        let album = match repo.get_last_genre_and_added_by().await {
+        let album = match repo.get_current().await {
             Ok(a) => a,
             Err(e) => {
                 println!("{:?}", e);
@@ -324,16 +936,20 @@ mod test {
     #[allow(dead_code)]
     async fn test_getting_rows() -> Result<()> {
         env_logger::init();
-        let repo: Box<dyn AlbumRepo> = Box::new(GoogleSheetsAlbumRepo::default().await?);
+        let repo: Box<dyn AlbumRepo + Send + Sync> =
+            Box::new(GoogleSheetsAlbumRepo::default().await?);
 
-        let album = match repo.fetch_random_album().await {
+        let album = match repo
+            .fetch_random_album(&RuleSet::default(), &crate::draw::new_seed())
+            .await
+        {
             Ok(a) => a,
             Err(e) => {
                 println!("{:?}", e);
                 return Err(e);
             }
         };
-        println!("{}", album);
+        println!("{}", album.album);
         Ok(())
     }
 }
@@ -0,0 +1,301 @@
+use std::fmt::{Display, Formatter};
+
+use crate::albums::{Album, AlbumRepo};
+use crate::selection::{RuleSet, SelectionContext};
+
+use anyhow::Result;
+
+const PAGE_SIZE: usize = 10;
+/// How much of a typed filter is kept in a button's id, which Discord limits to
+/// 100 characters.
+const FILTER_LENGTH: usize = 30;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Eligibility {
+    #[default]
+    Any,
+    Eligible,
+    Ineligible,
+}
+
+impl Eligibility {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "yes" => Eligibility::Eligible,
+            "no" => Eligibility::Ineligible,
+            _ => Eligibility::Any,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Eligibility::Any => "any",
+            Eligibility::Eligible => "yes",
+            Eligibility::Ineligible => "no",
+        }
+    }
+}
+
+/// Which unplayed albums to show. Genre and submitter match any part of the
+/// album's, ignoring case.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BacklogFilter {
+    pub genre: Option<String>,
+    pub submitter: Option<String>,
+    pub eligibility: Eligibility,
+}
+
+fn contains(text: &str, part: &Option<String>) -> bool {
+    part.as_ref()
+        .is_none_or(|part| text.to_lowercase().contains(&part.to_lowercase()))
+}
+
+fn clip(text: &str) -> String {
+    text.trim()
+        .replace(':', " ")
+        .chars()
+        .take(FILTER_LENGTH)
+        .collect()
+}
+
+impl BacklogFilter {
+    pub fn new(genre: Option<&str>, submitter: Option<&str>, eligibility: Eligibility) -> Self {
+        let text = |value: Option<&str>| value.map(clip).filter(|value| !value.is_empty());
+        BacklogFilter {
+            genre: text(genre),
+            submitter: text(submitter),
+            eligibility,
+        }
+    }
+
+    fn matches(&self, entry: &Entry) -> bool {
+        let eligible = matches!(entry.status, Status::Eligible(_));
+        contains(&entry.album.genre, &self.genre)
+            && contains(&entry.album.added_by, &self.submitter)
+            && match self.eligibility {
+                Eligibility::Any => true,
+                Eligibility::Eligible => eligible,
+                Eligibility::Ineligible => !eligible,
+            }
+    }
+
+    /// The id for a button showing `page` under this filter:
+    /// `backlog:<page>:<eligibility>:<genre>:<submitter>`.
+    pub fn custom_id(&self, page: usize) -> String {
+        format!(
+            "backlog:{}:{}:{}:{}",
+            page,
+            self.eligibility.as_str(),
+            self.genre.as_deref().unwrap_or_default(),
+            self.submitter.as_deref().unwrap_or_default()
+        )
+    }
+
+    /// Reads the page and filter back out of a button id, without the `backlog:`.
+    pub fn from_custom_id(id: &str) -> Option<(usize, Self)> {
+        let mut parts = id.splitn(4, ':');
+        let page = parts.next()?.parse().ok()?;
+        let eligibility = Eligibility::parse(parts.next()?);
+        let genre = parts.next()?;
+        let submitter = parts.next()?;
+        let filter = BacklogFilter::new(Some(genre), Some(submitter), eligibility);
+        Some((page, filter))
+    }
+}
+
+impl Display for BacklogFilter {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(genre) = &self.genre {
+            parts.push(format!("genre {}", genre));
+        }
+        if let Some(submitter) = &self.submitter {
+            parts.push(format!("submitted by {}", submitter));
+        }
+        match self.eligibility {
+            Eligibility::Any => {}
+            Eligibility::Eligible => parts.push(String::from("eligible")),
+            Eligibility::Ineligible => parts.push(String::from("not eligible")),
+        }
+        if parts.is_empty() {
+            write!(f, "everything")
+        } else {
+            write!(f, "{}", parts.join(", "))
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Status {
+    /// Can be drawn next, with this chance.
+    Eligible(f64),
+    /// Kept out of the next draw by this rule.
+    Excluded(String),
+}
+
+/// An unplayed album and whether the next draw could pick it.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub album: Album,
+    pub status: Status,
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let album = &self.album;
+        match &self.status {
+            Status::Eligible(odds) => write!(
+                f,
+                "✅ **{}** by {} ({}, {}) {:.1}%",
+                album.name,
+                album.artist,
+                album.genre,
+                album.added_by,
+                odds * 100.0
+            ),
+            Status::Excluded(rule) => write!(
+                f,
+                "❌ **{}** by {} ({}, {}): {}",
+                album.name, album.artist, album.genre, album.added_by, rule
+            ),
+        }
+    }
+}
+
+/// One page of the backlog browser.
+#[derive(Clone, Debug)]
+pub struct Page {
+    pub entries: Vec<Entry>,
+    /// Which page this is, counting from 0.
+    pub number: usize,
+    pub pages: usize,
+    /// How many albums matched the filter.
+    pub total: usize,
+    /// Rules set aside because nothing fit them all.
+    pub relaxed: Vec<String>,
+}
+
+impl Page {
+    pub fn is_last(&self) -> bool {
+        self.number + 1 >= self.pages
+    }
+}
+
+/// Filters `entries` and cuts out page `number`, or the last page if there
+/// aren't that many.
+fn paginate(entries: Vec<Entry>, filter: &BacklogFilter, number: usize) -> Page {
+    let mut entries: Vec<Entry> = entries
+        .into_iter()
+        .filter(|entry| filter.matches(entry))
+        .collect();
+    let total = entries.len();
+    let pages = total.div_ceil(PAGE_SIZE).max(1);
+    let number = number.min(pages - 1);
+    let start = number * PAGE_SIZE;
+    let entries = entries.drain(start..total.min(start + PAGE_SIZE)).collect();
+    Page {
+        entries,
+        number,
+        pages,
+        total,
+        relaxed: Vec::new(),
+    }
+}
+
+/// Page `number` of the unplayed albums matching `filter`, in backlog order, each
+/// marked with whether the next draw under `rules` could pick it.
+pub async fn browse<R: AlbumRepo + Sync + ?Sized>(
+    repo: &R,
+    rules: &RuleSet,
+    filter: &BacklogFilter,
+    number: usize,
+) -> Result<Page> {
+    let albums = repo.list_albums().await?;
+    if albums.iter().all(|album| album.played_on.is_some()) {
+        return Ok(paginate(Vec::new(), filter, 0));
+    }
+    let context = SelectionContext::load(repo).await?;
+    let candidates = rules.candidates(&albums, &context)?;
+    let status = |album: &Album| {
+        let same = |other: &&Album| other.row == album.row;
+        candidates
+            .odds
+            .iter()
+            .find(|(other, _)| same(other))
+            .map(|(_, odds)| Status::Eligible(*odds))
+            .or_else(|| {
+                candidates
+                    .excluded
+                    .iter()
+                    .find(|(other, _)| same(other))
+                    .map(|(_, rule)| Status::Excluded(rule.clone()))
+            })
+    };
+    let entries = albums
+        .iter()
+        .filter_map(|album| {
+            status(album).map(|status| Entry {
+                album: album.clone(),
+                status,
+            })
+        })
+        .collect();
+    Ok(Page {
+        relaxed: candidates.relaxed.clone(),
+        ..paginate(entries, filter, number)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(genre: &str, added_by: &str, status: Status) -> Entry {
+        Entry {
+            album: Album {
+                genre: genre.to_owned(),
+                added_by: added_by.to_owned(),
+                ..Default::default()
+            },
+            status,
+        }
+    }
+
+    #[test]
+    fn test_backlog_is_filtered_and_paged() {
+        let mut entries = Vec::new();
+        for i in 0..25 {
+            let status = if i % 5 == 0 {
+                Status::Excluded(String::from("rotation"))
+            } else {
+                Status::Eligible(0.05)
+            };
+            let genre = if i % 2 == 0 { "Shoegaze" } else { "Jazz" };
+            entries.push(entry(genre, "Ann", status));
+        }
+
+        let page = paginate(entries.clone(), &BacklogFilter::default(), 2);
+        assert_eq!((page.number, page.pages, page.total), (2, 3, 25));
+        assert_eq!(page.entries.len(), 5);
+        assert!(page.is_last());
+
+        let filter = BacklogFilter::new(Some("gaze"), Some("ann"), Eligibility::Ineligible);
+        let page = paginate(entries, &filter, 7);
+        assert_eq!((page.number, page.total), (0, 3));
+        assert_eq!(
+            filter.to_string(),
+            "genre gaze, submitted by ann, not eligible"
+        );
+    }
+
+    #[test]
+    fn test_filters_survive_the_button_id() {
+        let filter = BacklogFilter::new(Some("Hip: Hop"), None, Eligibility::Eligible);
+        let id = filter.custom_id(3);
+        assert!(id.len() <= 100);
+        let (page, read) =
+            BacklogFilter::from_custom_id(id.strip_prefix("backlog:").unwrap()).unwrap();
+        assert_eq!(page, 3);
+        assert_eq!(read, filter);
+    }
+}
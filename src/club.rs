@@ -0,0 +1,1433 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::albums::{same_album, Album};
+use crate::draw::{derive_seed, new_seed, parse_seed, seed_hash, DrawRecord, Seed};
+use crate::selection::{same, RuleSet, SelectionContext};
+use crate::spotify::Spotify;
+use crate::BoxedAlbumRepo;
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use log::error;
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+/// State keys: the queued album, the seed whose hash was announced last, the
+/// re-rolls of the queued album, what the last commit changed, the veto vote on
+/// it, and the open poll.
+const PENDING_PICK: &str = "pending_pick";
+const NEXT_SEED: &str = "next_seed";
+const REROLLS: &str = "rerolls";
+const LAST_TRANSITION: &str = "last_transition";
+const VETO: &str = "veto";
+const POLL: &str = "poll";
+/// The longest a poll or veto vote can stay open. Much longer and working out
+/// when it closes overflows, and zero or less closes it straight away.
+const MAX_HOURS: i64 = 24 * 365;
+
+lazy_static! {
+    /// How many times the Reroll button can throw away the queued album before
+    /// someone has to confirm one.
+    static ref MAX_REROLLS: u32 = std::env::var("MAX_REROLLS")
+        .ok()
+        .and_then(|rerolls| rerolls.parse().ok())
+        .unwrap_or(2);
+    /// The share of active members that has to be beaten for a veto to pass.
+    static ref VETO_THRESHOLD: f64 = std::env::var("VETO_THRESHOLD")
+        .ok()
+        .and_then(|threshold| threshold.parse().ok())
+        // NaN would let one vote through, and 1 or more none at all
+        .filter(|threshold| (0.0..1.0).contains(threshold))
+        .unwrap_or(0.5);
+    /// How long after a commit the album can be vetoed.
+    static ref VETO_WINDOW: chrono::Duration = chrono::Duration::hours(
+        std::env::var("VETO_WINDOW_HOURS")
+            .ok()
+            .and_then(|hours| hours.parse::<i64>().ok())
+            .unwrap_or(24)
+            .clamp(1, MAX_HOURS)
+    );
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AlbumAndLink {
+    pub album: Album,
+    pub link: Option<String>,
+    pub relaxed: Vec<String>,
+    pub reset_rotation: bool,
+    pub draw: DrawRecord,
+}
+
+impl AlbumAndLink {
+    pub fn as_message(&self) -> String {
+        let message = if let Some(link) = &self.link {
+            format!("The next album is {} \n {}", self.album, link)
+        } else {
+            format!(
+                "The next album is {} \n I had some trouble finding it on Spotify though.",
+                self.album
+            )
+        };
+        if self.relaxed.is_empty() {
+            message
+        } else {
+            format!(
+                "{} \n Nothing fit every rule, so I {}.",
+                message,
+                self.relaxed.join(", then ")
+            )
+        }
+    }
+}
+
+/// How `/album next` picks the next album, from `CLUB_MODE`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClubMode {
+    /// One album is drawn and someone confirms it.
+    Random,
+    /// Several albums are drawn and the club votes between them.
+    Vote {
+        nominees: usize,
+        window: chrono::Duration,
+    },
+}
+
+impl ClubMode {
+    /// `random` (the default) or `vote`, which reads the number of nominees from
+    /// `NOMINEES` (default 3) and how long the poll stays open from
+    /// `POLL_HOURS` (default 24).
+    pub fn from_env() -> Result<Self> {
+        let number = |name: &str, default: i64| -> Result<i64> {
+            match std::env::var(name) {
+                Ok(value) => Ok(value.parse()?),
+                Err(_) => Ok(default),
+            }
+        };
+        match std::env::var("CLUB_MODE").as_deref() {
+            Ok("random") | Err(_) => Ok(ClubMode::Random),
+            Ok("vote") => Ok(ClubMode::Vote {
+                // Discord fits five buttons in a row
+                nominees: number("NOMINEES", 3)?.clamp(2, 5) as usize,
+                window: chrono::Duration::hours(number("POLL_HOURS", 24)?.clamp(1, MAX_HOURS)),
+            }),
+            Ok(other) => Err(anyhow!("Unknown club mode {}", other)),
+        }
+    }
+}
+
+/// A vote between several drawn albums for the next pick.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Poll {
+    pub nominees: Vec<AlbumAndLink>,
+    /// When voting closes, in RFC 3339.
+    pub closes_at: String,
+    /// Each voter's Discord ID and the position of the nominee they voted for.
+    pub votes: BTreeMap<String, usize>,
+    /// Where the poll was opened, for announcing the winner.
+    pub channel_id: u64,
+}
+
+impl Poll {
+    /// The hash of the first nominee's draw seed, which the vote buttons carry.
+    pub fn hash(&self) -> &str {
+        &self.nominees[0].draw.seed_hash
+    }
+
+    pub fn closes_at(&self) -> Result<chrono::DateTime<chrono::Utc>> {
+        Ok(chrono::DateTime::parse_from_rfc3339(&self.closes_at)?.into())
+    }
+
+    pub fn message(&self) -> String {
+        let mut message = String::from("Vote for the next album!");
+        for (i, nominee) in self.nominees.iter().enumerate() {
+            message.push_str(&format!("\n {}. {}", i + 1, nominee.album));
+            if let Some(link) = &nominee.link {
+                message.push_str(&format!(" {}", link));
+            }
+        }
+        if let Ok(closes_at) = self.closes_at() {
+            message.push_str(&format!(
+                "\n The poll closes <t:{}:R>.",
+                closes_at.timestamp()
+            ));
+        }
+        message
+    }
+
+    /// The nominee with the most votes. Ties go to whichever was drawn first, so
+    /// a poll nobody votes in picks the album a random draw would have.
+    fn winner(&self) -> usize {
+        let mut counts = vec![0; self.nominees.len()];
+        for choice in self.votes.values() {
+            counts[*choice] += 1;
+        }
+        (0..counts.len())
+            .rev()
+            .max_by_key(|i| counts[*i])
+            .unwrap_or(0)
+    }
+}
+
+/// The queued album, as offered by `/album next` before anyone confirms it.
+pub struct Proposal {
+    pub pending: AlbumAndLink,
+    pub rerolls_left: u32,
+    /// The hash of the seed the next re-roll draws with.
+    pub reroll_hash: String,
+    /// The draw a re-roll just threw away, revealed along with the new one.
+    pub replaced: Option<DrawRecord>,
+}
+
+impl Proposal {
+    pub fn message(&self) -> String {
+        let album = match &self.pending.link {
+            Some(link) => format!("{} \n {}", self.pending.album, link),
+            None => self.pending.album.to_string(),
+        };
+        let rerolls = match self.rerolls_left {
+            0 => String::from("No re-rolls left."),
+            1 => String::from("1 re-roll left."),
+            n => format!("{} re-rolls left.", n),
+        };
+        let mut message = format!(
+            "Up next, once someone confirms: {} \n {} A re-roll draws with the seed hashing to {}.",
+            album, rerolls, self.reroll_hash
+        );
+        if let Some(replaced) = &self.replaced {
+            message.push_str(&format!(
+                " \n The album re-rolled away was drawn with seed {} (hash {}).",
+                replaced.seed, replaced.seed_hash
+            ));
+        }
+        message
+    }
+}
+
+/// How many times the queued album has been re-rolled with the button, and the
+/// seed for the next re-roll once its hash has gone out with a proposal.
+#[derive(Default, Serialize, Deserialize)]
+struct Rerolls {
+    count: u32,
+    seed: Option<String>,
+}
+
+/// What the last `/album next` changed, kept so an admin can undo it.
+#[derive(Serialize, Deserialize)]
+struct Transition {
+    committed: AlbumAndLink,
+    /// The rotation from before the commit appended to or cleared it.
+    rotation: Vec<String>,
+    /// Whether the rotation filled up and was cleared once the album's submitter
+    /// went in. A relaxed draw's reset before that is on `committed`.
+    #[serde(default)]
+    cleared_rotation: bool,
+}
+
+/// What confirming a pick did.
+#[derive(Debug)]
+pub enum Next {
+    /// The queued album was committed and announced.
+    Committed {
+        announcement: String,
+        album: Box<Album>,
+        /// The hash of its draw's seed, which the Veto button carries.
+        hash: String,
+        /// Whether the rotation was emptied before the submitter went in, because
+        /// the draw had to set the rotation rule aside.
+        reset_rotation: bool,
+        /// Whether the rotation filled up and was cleared after the submitter went
+        /// in.
+        cleared_rotation: bool,
+    },
+    /// Someone else already confirmed the same pick, so nothing changed.
+    Debounced(String),
+}
+
+impl Next {
+    pub fn message(&self) -> String {
+        match self {
+            Next::Committed { announcement, .. } => announcement.clone(),
+            Next::Debounced(announcement) => {
+                format!("Someone beat you to it! {}", announcement)
+            }
+        }
+    }
+}
+
+/// What `/album undo` did.
+#[derive(Debug)]
+pub struct Undo {
+    /// The album taken back and queued up again.
+    pub album: Album,
+    /// The draw prefetched after the commit, thrown away to make room.
+    pub dropped: Option<DrawRecord>,
+    /// The hash of the seed the next draw will use, if it's been announced.
+    pub next_hash: Option<String>,
+}
+
+impl Undo {
+    pub fn message(&self) -> String {
+        let mut message = format!(
+            "Took back {}. It's queued up again for the next /album next.",
+            self.album
+        );
+        message.push_str(&dropped_message(
+            self.dropped.as_ref(),
+            self.next_hash.as_ref(),
+        ));
+        message
+    }
+}
+
+/// Reveals the seed of a prefetched draw thrown away by an undo or veto, along
+/// with the hash of the fresh seed replacing it.
+fn dropped_message(dropped: Option<&DrawRecord>, next_hash: Option<&String>) -> String {
+    match (dropped, next_hash) {
+        (Some(dropped), Some(next_hash)) => format!(
+            " \n The draw prefetched after it is dropped: its seed was {} (hash {}). \
+            The seed for the next draw now hashes to {}.",
+            dropped.seed, dropped.seed_hash, next_hash
+        ),
+        _ => String::new(),
+    }
+}
+
+/// The veto vote on the last committed album.
+#[derive(Clone, Serialize, Deserialize)]
+struct Veto {
+    /// The hash of the vetoed pick's draw seed, which the Veto button carries.
+    hash: String,
+    album: Album,
+    /// When the vote closes, in RFC 3339.
+    closes_at: String,
+    /// Each voter's Discord ID and the member they voted as.
+    #[serde(default)]
+    votes: BTreeMap<String, String>,
+    /// Whether the vote passed, which keeps the album out of the draw replacing it.
+    passed: bool,
+}
+
+/// What a vote to veto did.
+pub enum VetoOutcome {
+    /// The vote was counted, but there aren't enough yet.
+    Counted {
+        album: Album,
+        votes: usize,
+        needed: usize,
+    },
+    /// The vote passed, so the album went back to the pool. Its replacement is
+    /// drawn by the next /album next.
+    Passed {
+        album: Album,
+        /// The draw prefetched after the commit, thrown away like on an undo.
+        dropped: Option<Box<DrawRecord>>,
+        /// The hash of the seed the replacement will be drawn with.
+        next_hash: Option<String>,
+    },
+}
+
+impl VetoOutcome {
+    pub fn message(&self, voter: &str) -> String {
+        match self {
+            VetoOutcome::Counted {
+                album,
+                votes,
+                needed,
+            } => format!(
+                "{} voted to veto {} ({} of {} votes needed).",
+                voter, album, votes, needed
+            ),
+            VetoOutcome::Passed {
+                album,
+                dropped,
+                next_hash,
+            } => format!(
+                "{} has been vetoed and goes back to the pool. Run /album next to draw its \
+                replacement.{}",
+                album,
+                dropped_message(dropped.as_deref(), next_hash.as_ref())
+            ),
+        }
+    }
+}
+
+/// Where the club is between draws. Confirming a pick moves it from `Pending`
+/// to `Committed` and on to the next album being prefetched, back in
+/// `Pending`, all while holding the lock.
+pub enum ClubState {
+    /// Nothing is queued, because the bot is booting or drawing failed.
+    Empty,
+    /// An album has been drawn and is waiting to be confirmed.
+    Pending(Box<AlbumAndLink>),
+    /// The pending album has just been written to the backend, and the next one
+    /// hasn't been drawn yet.
+    Committed,
+}
+
+pub struct Cycle {
+    state: ClubState,
+    /// The seed for the upcoming draw, once its hash has been announced.
+    next_seed: Option<Seed>,
+    /// The seed hash and announcement of the last commit, for answering anyone
+    /// who confirms it again.
+    last_commit: Option<(String, String)>,
+    /// How many times the queued album has been re-rolled with the button.
+    rerolls: u32,
+    /// The seed for the next re-roll, once its hash has been announced.
+    reroll_seed: Option<Seed>,
+    veto: Option<Veto>,
+    poll: Option<Poll>,
+}
+
+/// The club's draw cycle. Every change to it goes through the lock on `cycle`,
+/// so only one transition runs at a time.
+#[derive(Clone)]
+pub struct Club {
+    repo: Arc<BoxedAlbumRepo>,
+    rules: Arc<RuleSet>,
+    cycle: Arc<Mutex<Cycle>>,
+}
+
+impl Club {
+    pub fn new(repo: Arc<BoxedAlbumRepo>, rules: Arc<RuleSet>) -> Self {
+        Club {
+            repo,
+            rules,
+            cycle: Arc::new(Mutex::new(Cycle {
+                state: ClubState::Empty,
+                next_seed: None,
+                last_commit: None,
+                rerolls: 0,
+                reroll_seed: None,
+                veto: None,
+                poll: None,
+            })),
+        }
+    }
+
+    async fn store_next_seed(&self, seed: &Seed) {
+        let seed = hex::encode(seed);
+        if let Err(e) = self.repo.set_state(NEXT_SEED, Some(&seed)).await {
+            error!("Error saving the next seed {:?}", e);
+        }
+    }
+
+    async fn store_rerolls(&self, cycle: &Cycle) -> Result<()> {
+        let rerolls = Rerolls {
+            count: cycle.rerolls,
+            seed: cycle.reroll_seed.as_ref().map(hex::encode),
+        };
+        let rerolls = serde_json::to_string(&rerolls)?;
+        self.repo.set_state(REROLLS, Some(&rerolls)).await
+    }
+
+    /// Starts the Reroll button's count over, for a newly queued album.
+    async fn reset_rerolls(&self, cycle: &mut Cycle) {
+        cycle.rerolls = 0;
+        if let Err(e) = self.store_rerolls(cycle).await {
+            error!("Error saving the re-rolls {:?}", e);
+        }
+    }
+
+    async fn store_veto(&self, veto: Option<&Veto>) {
+        let result = match veto.map(serde_json::to_string).transpose() {
+            Ok(veto) => self.repo.set_state(VETO, veto.as_deref()).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            error!("Error saving the veto vote {:?}", e);
+        }
+    }
+
+    async fn store_poll(&self, poll: Option<&Poll>) {
+        let result = match poll.map(serde_json::to_string).transpose() {
+            Ok(poll) => self.repo.set_state(POLL, poll.as_deref()).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            error!("Error saving the poll {:?}", e);
+        }
+    }
+
+    /// Draws an album with `seed`, leaving out the albums in `exclude`.
+    async fn draw_with(
+        &self,
+        cycle: &Cycle,
+        seed: &Seed,
+        exclude: &[&Album],
+    ) -> Result<AlbumAndLink> {
+        let mut exclude = exclude.to_vec();
+        // A vetoed album goes back to the pool, but not for the draw replacing it
+        if let Some(veto) = cycle.veto.as_ref().filter(|veto| veto.passed) {
+            exclude.push(&veto.album);
+        }
+        let pick = if exclude.is_empty() {
+            self.repo.fetch_random_album(&self.rules, seed).await?
+        } else {
+            let mut albums = self.repo.list_albums().await?;
+            albums.retain(|album| !exclude.iter().any(|excluded| same_album(album, excluded)));
+            let context = SelectionContext::load(&**self.repo).await?;
+            self.rules.select(&albums, &context, seed)?
+        };
+        let url = Spotify::fetch_album_link(&pick.album)
+            .await
+            .map_err(|e| error!("Error getting spotify url {:?}", e))
+            .ok();
+        Ok(AlbumAndLink {
+            album: pick.album,
+            link: url.flatten(),
+            relaxed: pick.relaxed,
+            reset_rotation: pick.reset_rotation,
+            draw: pick.draw,
+        })
+    }
+
+    /// Draws the next album with the announced seed and queues it.
+    async fn draw(&self, cycle: &mut Cycle) -> Result<()> {
+        let seed = cycle.next_seed.ok_or_else(|| {
+            anyhow!("The next draw's seed hasn't been announced yet, so nothing can be drawn")
+        })?;
+        let next_album = self.draw_with(cycle, &seed, &[]).await?;
+        let pending = serde_json::to_string(&next_album)?;
+        if let Err(e) = self.repo.set_state(PENDING_PICK, Some(&pending)).await {
+            error!("Error saving the next album {:?}", e);
+        }
+        cycle.state = ClubState::Pending(Box::new(next_album));
+        Ok(())
+    }
+
+    /// Picks the queued album back up after a restart, only drawing a new one if
+    /// nothing was queued and the seed for it was announced.
+    pub async fn resume(&self) -> Result<()> {
+        let mut cycle = self.cycle.lock().await;
+        if let Some(seed) = self.repo.get_state(NEXT_SEED).await? {
+            cycle.next_seed = Some(parse_seed(&seed)?);
+        }
+        if let Some(veto) = self.repo.get_state(VETO).await? {
+            cycle.veto = Some(serde_json::from_str(&veto)?);
+        }
+        if let Some(poll) = self.repo.get_state(POLL).await? {
+            cycle.poll = Some(serde_json::from_str(&poll)?);
+        }
+        if let Some(rerolls) = self.repo.get_state(REROLLS).await? {
+            let rerolls: Rerolls = serde_json::from_str(&rerolls)?;
+            cycle.rerolls = rerolls.count;
+            cycle.reroll_seed = rerolls.seed.as_deref().map(parse_seed).transpose()?;
+        }
+        match self.repo.get_state(PENDING_PICK).await? {
+            Some(pending) => {
+                cycle.state = ClubState::Pending(serde_json::from_str(&pending)?);
+                Ok(())
+            }
+            None if cycle.next_seed.is_some() => self.draw(&mut cycle).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Commits to a seed for the next draw if there isn't one yet, such as on a
+    /// club's first boot, returning its hash to announce. Nothing is drawn until
+    /// the hash has gone out, so nobody can see what a seed picks beforehand.
+    pub async fn announce_seed(&self) -> Result<Option<String>> {
+        let mut cycle = self.cycle.lock().await;
+        if cycle.next_seed.is_some() {
+            return Ok(None);
+        }
+        let seed = new_seed();
+        self.repo
+            .set_state(NEXT_SEED, Some(&hex::encode(seed)))
+            .await?;
+        cycle.next_seed = Some(seed);
+        Ok(Some(format!(
+            "The seed for the next draw hashes to {}. Run /album next again to draw with it.",
+            seed_hash(&seed)
+        )))
+    }
+
+    /// The seed for the next re-roll, committing to a new one if the last was
+    /// used. It has to be saved before its hash goes out, or a restart would
+    /// re-roll with a different seed from the one announced.
+    async fn reroll_seed(&self, cycle: &mut Cycle) -> Result<Seed> {
+        if let Some(seed) = cycle.reroll_seed {
+            return Ok(seed);
+        }
+        let seed = new_seed();
+        cycle.reroll_seed = Some(seed);
+        if let Err(e) = self.store_rerolls(cycle).await {
+            cycle.reroll_seed = None;
+            return Err(e);
+        }
+        Ok(seed)
+    }
+
+    /// Throws the queued album away and draws another with the announced re-roll
+    /// seed, returning the draw thrown away.
+    async fn redraw(&self, cycle: &mut Cycle) -> Result<Option<DrawRecord>> {
+        let seed = cycle
+            .reroll_seed
+            .ok_or_else(|| anyhow!("The re-roll seed hasn't been announced yet"))?;
+        let next_album = self.draw_with(cycle, &seed, &[]).await?;
+        let pending = serde_json::to_string(&next_album)?;
+        self.repo.set_state(PENDING_PICK, Some(&pending)).await?;
+        let replaced =
+            std::mem::replace(&mut cycle.state, ClubState::Pending(Box::new(next_album)));
+        // Each seed draws once, so the next re-roll gets a fresh one
+        cycle.reroll_seed = None;
+        Ok(match replaced {
+            ClubState::Pending(replaced) => Some(replaced.draw),
+            _ => None,
+        })
+    }
+
+    /// The queued album as a proposal, announcing the seed a re-roll would use.
+    async fn proposal(&self, cycle: &mut Cycle) -> Result<Proposal> {
+        let reroll_hash = seed_hash(&self.reroll_seed(cycle).await?);
+        match &cycle.state {
+            ClubState::Pending(pending) => Ok(Proposal {
+                pending: pending.as_ref().clone(),
+                rerolls_left: MAX_REROLLS.saturating_sub(cycle.rerolls),
+                reroll_hash,
+                replaced: None,
+            }),
+            _ => Err(anyhow!("There's no album queued")),
+        }
+    }
+
+    /// The queued album waiting to be confirmed, drawing one if there isn't one.
+    pub async fn propose(&self) -> Result<Proposal> {
+        let mut cycle = self.cycle.lock().await;
+        if !matches!(cycle.state, ClubState::Pending(_)) {
+            self.draw(&mut cycle).await?;
+        }
+        self.proposal(&mut cycle).await
+    }
+
+    /// Re-rolls the queued album for the admin command, without showing what
+    /// comes out. Doesn't count towards the Reroll button's limit. If no re-roll
+    /// seed has been announced, announces one instead. Returns what to tell the
+    /// admin and whether anything was re-rolled.
+    pub async fn reroll(&self) -> Result<(String, bool)> {
+        let mut cycle = self.cycle.lock().await;
+        if cycle.reroll_seed.is_none() {
+            let seed = self.reroll_seed(&mut cycle).await?;
+            let message = format!(
+                "The seed for the re-roll hashes to {}. Run /album reroll again to re-roll with it.",
+                seed_hash(&seed)
+            );
+            return Ok((message, false));
+        }
+        let replaced = self.redraw(&mut cycle).await?;
+        let next = seed_hash(&self.reroll_seed(&mut cycle).await?);
+        let mut message = String::from("Re-rolled the next album with the announced re-roll seed.");
+        if let Some(replaced) = replaced {
+            message.push_str(&format!(
+                " The draw it replaced had seed {} (hash {}).",
+                replaced.seed, replaced.seed_hash
+            ));
+        }
+        message.push_str(&format!(" The next re-roll's seed hashes to {}.", next));
+        Ok((message, true))
+    }
+
+    /// Re-rolls the proposed album whose draw's seed hashed to `hash`, if it's
+    /// still queued and there are re-rolls left. Returns the album thrown away
+    /// along with the new proposal.
+    pub async fn reroll_proposal(&self, hash: &str) -> Result<(Album, Proposal)> {
+        let mut cycle = self.cycle.lock().await;
+        let replaced = queued(&cycle, hash)?.album.clone();
+        if cycle.rerolls >= *MAX_REROLLS {
+            return Err(anyhow!(
+                "There are no re-rolls left, so this one has to be confirmed."
+            ));
+        }
+        let replaced_draw = self.redraw(&mut cycle).await?;
+        cycle.rerolls += 1;
+        let proposal = self.proposal(&mut cycle).await?;
+        if let Err(e) = self.store_rerolls(&cycle).await {
+            error!("Error saving the re-rolls {:?}", e);
+        }
+        Ok((
+            replaced,
+            Proposal {
+                replaced: replaced_draw,
+                ..proposal
+            },
+        ))
+    }
+
+    /// Writes a pending album to the backend. If any step fails, the ones that
+    /// already went through are undone.
+    async fn commit(&self, pending: &AlbumAndLink, serialized: &str) -> Result<Transition> {
+        let repo = &self.repo;
+        let album = &pending.album;
+        let rotation = repo.list_rotation().await?;
+        // An undone commit or a poll already recorded its draw
+        let recorded = repo.list_draws().await?.contains(&pending.draw);
+        let (mut played, mut current, mut cleared_rotation) = (false, false, false);
+        let result: Result<()> = async {
+            repo.set_state(PENDING_PICK, None).await?;
+            if pending.reset_rotation {
+                repo.set_rotation(&[]).await?;
+            }
+            // A full rotation is cleared as soon as the last name goes in
+            cleared_rotation = repo.add_name_to_rotation(album.added_by.clone()).await?;
+            repo.mark_played(album).await?;
+            played = true;
+            repo.set_current(album).await?;
+            current = true;
+            if !recorded {
+                repo.add_draw(&pending.draw).await?;
+            }
+            Ok(())
+        }
+        .await;
+        let e = match result {
+            Ok(()) => {
+                return Ok(Transition {
+                    committed: pending.clone(),
+                    rotation,
+                    cleared_rotation,
+                })
+            }
+            Err(e) => e,
+        };
+        let rollback = async {
+            if current {
+                repo.remove_current(album).await?;
+            }
+            if played {
+                repo.unmark_played(album).await?;
+            }
+            repo.set_rotation(&rotation).await?;
+            repo.set_state(PENDING_PICK, Some(serialized)).await
+        }
+        .await;
+        Err(match rollback {
+            Ok(()) => anyhow!(
+                "I couldn't commit {}: {:#}. Nothing was changed, so it's safe to try again.",
+                album,
+                e
+            ),
+            Err(rollback) => anyhow!(
+                "I couldn't commit {}: {:#}. Undoing the half-finished commit failed too ({:#}), \
+                so the club's data needs checking by hand.",
+                album,
+                e,
+                rollback
+            ),
+        })
+    }
+
+    /// Announces and commits the queued album whose draw's seed hashed to
+    /// `hash`, then prefetches the next one. The lock is held from start to
+    /// finish, so each album is committed exactly once.
+    pub async fn confirm(&self, hash: &str) -> Result<Next> {
+        let cycle = self.cycle.clone().lock_owned().await;
+        if let Some((committed, announcement)) = &cycle.last_commit {
+            if committed == hash {
+                return Ok(Next::Debounced(announcement.clone()));
+            }
+        }
+        let pending = queued(&cycle, hash)?.clone();
+        self.commit_queued(cycle, pending).await
+    }
+
+    /// Commits `pending`, which has to be the queued album, and prefetches the
+    /// next one.
+    async fn commit_queued(
+        &self,
+        mut cycle: OwnedMutexGuard<Cycle>,
+        pending: AlbumAndLink,
+    ) -> Result<Next> {
+        let serialized = serde_json::to_string(&pending)?;
+        let transition = self.commit(&pending, &serialized).await?;
+        let cleared_rotation = transition.cleared_rotation;
+        let transition = serde_json::to_string(&transition)?;
+        if let Err(e) = self
+            .repo
+            .set_state(LAST_TRANSITION, Some(&transition))
+            .await
+        {
+            error!(
+                "Error saving the last commit, so it can't be undone {:?}",
+                e
+            );
+        }
+
+        // Commit to the next draw's seed before anyone sees what it picks
+        let next_seed = new_seed();
+        cycle.next_seed = Some(next_seed);
+        self.store_next_seed(&next_seed).await;
+        let announcement = format!(
+            "{} \n Drawn with seed {} (hash {}). The seed for the next draw hashes to {}.",
+            pending.as_message(),
+            pending.draw.seed,
+            pending.draw.seed_hash,
+            seed_hash(&next_seed)
+        );
+        cycle.last_commit = Some((pending.draw.seed_hash.clone(), announcement.clone()));
+        self.reset_rerolls(&mut cycle).await;
+        let veto = Veto {
+            hash: pending.draw.seed_hash.clone(),
+            album: pending.album.clone(),
+            closes_at: (chrono::Utc::now() + *VETO_WINDOW).to_rfc3339(),
+            votes: BTreeMap::new(),
+            passed: false,
+        };
+        self.store_veto(Some(&veto)).await;
+        cycle.veto = Some(veto);
+        if cycle.poll.take().is_some() {
+            self.store_poll(None).await;
+        }
+        cycle.state = ClubState::Committed;
+        self.prefetch(cycle);
+        Ok(Next::Committed {
+            announcement,
+            album: Box::new(pending.album),
+            hash: pending.draw.seed_hash,
+            reset_rotation: pending.reset_rotation,
+            cleared_rotation,
+        })
+    }
+
+    /// The poll that's open, if there is one.
+    pub async fn poll(&self) -> Option<Poll> {
+        self.cycle.lock().await.poll.clone()
+    }
+
+    /// Opens a poll between `nominees` albums, the queued one and more drawn
+    /// with seeds derived from its seed. If a poll is already open, returns
+    /// that one instead, along with `false`.
+    pub async fn open_poll(
+        &self,
+        nominees: usize,
+        window: chrono::Duration,
+        channel_id: u64,
+    ) -> Result<(Poll, bool)> {
+        let mut cycle = self.cycle.lock().await;
+        if let Some(poll) = &cycle.poll {
+            return Ok((poll.clone(), false));
+        }
+        if !matches!(cycle.state, ClubState::Pending(_)) {
+            self.draw(&mut cycle).await?;
+        }
+        let first = match &cycle.state {
+            ClubState::Pending(pending) => pending.as_ref().clone(),
+            _ => return Err(anyhow!("There's no album queued")),
+        };
+        let seed = parse_seed(&first.draw.seed)?;
+        let unplayed = self
+            .repo
+            .list_albums()
+            .await?
+            .iter()
+            .filter(|album| album.played_on.is_none())
+            .count();
+        let mut picks = vec![first];
+        for i in 1..nominees.min(unplayed) {
+            let exclude: Vec<&Album> = picks.iter().map(|pick| &pick.album).collect();
+            let pick = self
+                .draw_with(&cycle, &derive_seed(&seed, i), &exclude)
+                .await?;
+            picks.push(pick);
+        }
+        // Every nominee's draw is recorded, not just the winner's, so the losing
+        // ones can be checked too
+        let recorded = self.repo.list_draws().await?;
+        for pick in &picks {
+            if !recorded.contains(&pick.draw) {
+                self.repo.add_draw(&pick.draw).await?;
+            }
+        }
+        let poll = Poll {
+            nominees: picks,
+            closes_at: (chrono::Utc::now() + window).to_rfc3339(),
+            votes: BTreeMap::new(),
+            channel_id,
+        };
+        self.store_poll(Some(&poll)).await;
+        cycle.poll = Some(poll.clone());
+        Ok((poll, true))
+    }
+
+    /// Counts `voter`'s vote for the `choice`th nominee of the poll whose first
+    /// nominee's seed hashed to `hash`. Voting again changes the vote.
+    pub async fn vote(&self, hash: &str, voter: &str, choice: usize) -> Result<Album> {
+        let mut cycle = self.cycle.lock().await;
+        let poll = match &mut cycle.poll {
+            Some(poll) if poll.hash() == hash => poll,
+            _ => return Err(anyhow!("That poll has closed.")),
+        };
+        if chrono::Utc::now() > poll.closes_at()? {
+            return Err(anyhow!("That poll has closed."));
+        }
+        let album = match poll.nominees.get(choice) {
+            Some(nominee) => nominee.album.clone(),
+            None => return Err(anyhow!("That album isn't in the poll.")),
+        };
+        poll.votes.insert(voter.to_owned(), choice);
+        let poll = poll.clone();
+        self.store_poll(Some(&poll)).await;
+        Ok(album)
+    }
+
+    /// Closes the poll whose first nominee's seed hashed to `hash` and commits
+    /// the winner. If the commit fails the poll stays open, so closing it can be
+    /// tried again.
+    pub async fn close_poll(&self, hash: &str) -> Result<Next> {
+        let mut cycle = self.cycle.clone().lock_owned().await;
+        let winner = match &cycle.poll {
+            Some(poll) if poll.hash() == hash => poll.nominees[poll.winner()].clone(),
+            _ => return Err(anyhow!("That poll has already closed.")),
+        };
+        let pending = serde_json::to_string(&winner)?;
+        self.repo.set_state(PENDING_PICK, Some(&pending)).await?;
+        cycle.state = ClubState::Pending(Box::new(winner.clone()));
+        self.commit_queued(cycle, winner).await
+    }
+
+    /// Takes back the last commit: the album comes off the ratings history and
+    /// out of the played list, the rotation goes back to how it was, and the
+    /// album is queued up again in place of whatever was prefetched. Returns
+    /// what was undone, if there was anything to undo.
+    pub async fn undo(&self) -> Result<Option<Undo>> {
+        let mut cycle = self.cycle.lock().await;
+        let transition = match self.take_back(&mut cycle).await? {
+            Some(transition) => transition,
+            None => return Ok(None),
+        };
+        let pending = serde_json::to_string(&transition.committed)?;
+        self.repo.set_state(PENDING_PICK, Some(&pending)).await?;
+        let replaced = std::mem::replace(
+            &mut cycle.state,
+            ClubState::Pending(Box::new(transition.committed.clone())),
+        );
+        // The prefetched draw's seed hash was announced, so it's revealed rather
+        // than quietly dropped, and the next draw gets a seed of its own
+        let dropped = match replaced {
+            ClubState::Pending(dropped) => {
+                let next_seed = new_seed();
+                cycle.next_seed = Some(next_seed);
+                self.store_next_seed(&next_seed).await;
+                Some(dropped.draw)
+            }
+            _ => None,
+        };
+        Ok(Some(Undo {
+            album: transition.committed.album,
+            dropped,
+            next_hash: cycle.next_seed.as_ref().map(seed_hash),
+        }))
+    }
+
+    /// Reverts the last commit in the backend, leaving the queue alone. If any
+    /// step fails, the ones that already went through are put back, so the undo
+    /// can be tried again.
+    async fn take_back(&self, cycle: &mut Cycle) -> Result<Option<Transition>> {
+        let repo = &self.repo;
+        let transition: Transition = match repo.get_state(LAST_TRANSITION).await? {
+            Some(transition) => serde_json::from_str(&transition)?,
+            None => return Ok(None),
+        };
+        let album = &transition.committed.album;
+        let rotation = repo.list_rotation().await?;
+        let current = repo.list_ratings().await?.into_iter().next();
+        let played_on = repo
+            .list_albums()
+            .await?
+            .into_iter()
+            .find(|other| same_album(other, album))
+            .and_then(|other| other.played_on);
+        let (mut removed, mut unplayed) = (false, false);
+        let result: Result<()> = async {
+            repo.remove_current(album).await?;
+            removed = true;
+            repo.unmark_played(album).await?;
+            unplayed = true;
+            repo.set_rotation(&transition.rotation).await?;
+            repo.set_state(LAST_TRANSITION, None).await
+        }
+        .await;
+        if let Err(e) = result {
+            let rollback = async {
+                repo.set_rotation(&rotation).await?;
+                if unplayed {
+                    let played = Album {
+                        played_on: played_on.clone(),
+                        ..album.clone()
+                    };
+                    repo.mark_played(&played).await?;
+                }
+                if let (true, Some(current)) = (removed, &current) {
+                    repo.add_ratings(std::slice::from_ref(current)).await?;
+                }
+                Ok::<(), anyhow::Error>(())
+            }
+            .await;
+            return Err(match rollback {
+                Ok(()) => anyhow!(
+                    "I couldn't undo {}: {:#}. Nothing was changed, so it's safe to try again.",
+                    album,
+                    e
+                ),
+                Err(rollback) => anyhow!(
+                    "I couldn't undo {}: {:#}. Putting things back failed too ({:#}), so the \
+                    club's data needs checking by hand.",
+                    album,
+                    e,
+                    rollback
+                ),
+            });
+        }
+        cycle.last_commit = None;
+        self.reset_rerolls(cycle).await;
+        if cycle.veto.take().is_some() {
+            self.store_veto(None).await;
+        }
+        Ok(Some(transition))
+    }
+
+    /// Counts a vote to veto the committed album whose draw's seed hashed to
+    /// `hash`, from the Discord user `voter_id` going by `voter_name`. Only active
+    /// members can vote, once each. Members who have submitted through the bot
+    /// are matched by their Discord ID, and the rest by name. Once enough of them
+    /// have voted, the commit is taken back and the announced next draw is made
+    /// without the album once someone runs /album next.
+    pub async fn veto(&self, hash: &str, voter_id: &str, voter_name: &str) -> Result<VetoOutcome> {
+        let members: Vec<String> = self
+            .repo
+            .list_members()
+            .await?
+            .into_iter()
+            .filter(|member| member.active)
+            .map(|member| member.name)
+            .collect();
+        // Names can be copied, so a member known by their Discord ID can only
+        // vote from that account
+        let ids: Vec<(String, String)> = self
+            .repo
+            .list_albums()
+            .await?
+            .into_iter()
+            .filter_map(|album| album.submitter_id.map(|id| (album.added_by, id)))
+            .collect();
+        let id_of = |member: &str| {
+            ids.iter()
+                .find(|(name, _)| same(name, member))
+                .map(|(_, id)| id.as_str())
+        };
+        let voter = members
+            .iter()
+            .find(|member| id_of(member) == Some(voter_id))
+            .or_else(|| {
+                members
+                    .iter()
+                    .find(|member| same(member, voter_name) && id_of(member).is_none())
+            })
+            .cloned();
+        let needed = vetoes_needed(members.len());
+        let mut cycle = self.cycle.lock().await;
+        let veto = match &mut cycle.veto {
+            Some(veto) if veto.hash == hash && !veto.passed => veto,
+            _ => return Err(anyhow!("That album can't be vetoed any more.")),
+        };
+        let closes_at = chrono::DateTime::parse_from_rfc3339(&veto.closes_at)?;
+        if chrono::Utc::now() > closes_at {
+            return Err(anyhow!("The vote to veto {} has closed.", veto.album));
+        }
+        let voter = match voter {
+            Some(member) => member,
+            None => return Err(anyhow!("Only active members can vote to veto.")),
+        };
+        if veto.votes.contains_key(voter_id) || veto.votes.values().any(|v| same(v, &voter)) {
+            return Err(anyhow!("You've already voted to veto {}.", veto.album));
+        }
+        veto.votes.insert(voter_id.to_owned(), voter);
+        let (album, votes) = (veto.album.clone(), veto.votes.len());
+        let mut veto = veto.clone();
+        if votes < needed {
+            self.store_veto(Some(&veto)).await;
+            return Ok(VetoOutcome::Counted {
+                album,
+                votes,
+                needed,
+            });
+        }
+
+        if self.take_back(&mut cycle).await?.is_none() {
+            self.store_veto(Some(&veto)).await;
+            return Err(anyhow!(
+                "The vote to veto {} passed, but there's no record of its commit to take back. \
+                An admin will have to sort it out.",
+                album
+            ));
+        }
+        veto.passed = true;
+        self.store_veto(Some(&veto)).await;
+        cycle.veto = Some(veto);
+        // As with an undo, the prefetched draw's seed hash was announced, so it's
+        // revealed rather than quietly drawn over, and the replacement gets a seed
+        // of its own
+        if let Err(e) = self.repo.set_state(PENDING_PICK, None).await {
+            error!("Error clearing the prefetched album {:?}", e);
+        }
+        let dropped = match std::mem::replace(&mut cycle.state, ClubState::Empty) {
+            ClubState::Pending(dropped) => {
+                let next_seed = new_seed();
+                cycle.next_seed = Some(next_seed);
+                self.store_next_seed(&next_seed).await;
+                Some(Box::new(dropped.draw))
+            }
+            _ => None,
+        };
+        Ok(VetoOutcome::Passed {
+            album,
+            dropped,
+            next_hash: cycle.next_seed.as_ref().map(seed_hash),
+        })
+    }
+
+    /// Draws the next album in the background, keeping the lock until it's done.
+    fn prefetch(&self, mut cycle: OwnedMutexGuard<Cycle>) {
+        let club = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = club.draw(&mut cycle).await {
+                error!("Error drawing the next album {:?}", e);
+            }
+        });
+    }
+}
+
+/// How many votes it takes to veto an album: more than `VETO_THRESHOLD` of the
+/// `members` active members.
+fn vetoes_needed(members: usize) -> usize {
+    ((members as f64 * *VETO_THRESHOLD) as usize + 1).min(members.max(1))
+}
+
+/// The queued album, as long as it's the one whose draw's seed hashed to `hash`.
+fn queued<'a>(cycle: &'a Cycle, hash: &str) -> Result<&'a AlbumAndLink> {
+    match &cycle.state {
+        ClubState::Pending(pending) if pending.draw.seed_hash == hash => Ok(pending),
+        _ => Err(anyhow!(
+            "That pick isn't queued any more. Run /album next to see the one that is."
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::albums::{AlbumRepo, Member, Rating};
+    use crate::sqlite::SqliteAlbumRepo;
+
+    async fn unannounced_club() -> Result<Club> {
+        let repo = SqliteAlbumRepo::open(":memory:")?;
+        let album = |name: &str, genre: &str, added_by: &str| Album {
+            name: name.to_owned(),
+            artist: "Someone".to_owned(),
+            genre: genre.to_owned(),
+            added_by: added_by.to_owned(),
+            ..Default::default()
+        };
+        let members: Vec<Member> = ["Ann", "Bob", "Cat"]
+            .iter()
+            .map(|name| Member {
+                name: name.to_string(),
+                active: true,
+                joined: None,
+            })
+            .collect();
+        repo.add_members(&members).await?;
+        repo.add_albums(&[
+            Album {
+                submitter_id: Some("1".to_owned()),
+                ..album("Syro", "Electronic", "Ann")
+            },
+            album("Souvlaki", "Shoegaze", "Bob"),
+        ])
+        .await?;
+        repo.add_ratings(&[Rating {
+            album: album("Trust", "Slowcore", "Cat"),
+            date: None,
+            extra: Vec::new(),
+        }])
+        .await?;
+        let repo: Arc<BoxedAlbumRepo> = Arc::new(Box::new(repo));
+        Ok(Club::new(repo, Arc::new(RuleSet::default())))
+    }
+
+    async fn club() -> Result<Club> {
+        let club = unannounced_club().await?;
+        club.announce_seed().await?;
+        Ok(club)
+    }
+
+    async fn queued_hash(club: &Club) -> Result<String> {
+        Ok(club.propose().await?.pending.draw.seed_hash)
+    }
+
+    #[tokio::test]
+    async fn test_nothing_is_drawn_before_the_seed_is_announced() -> Result<()> {
+        let club = unannounced_club().await?;
+        club.resume().await?;
+        assert!(club.propose().await.is_err());
+
+        let announcement = club.announce_seed().await?.unwrap();
+        assert!(club.announce_seed().await?.is_none());
+        let proposal = club.propose().await?;
+        assert!(announcement.contains(&proposal.pending.draw.seed_hash));
+
+        // After a restart, the announced seed is picked back up
+        let restarted = Club::new(club.repo.clone(), club.rules.clone());
+        restarted.resume().await?;
+        assert!(restarted.announce_seed().await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_picks_are_committed_once() -> Result<()> {
+        let club = club().await?;
+        club.resume().await?;
+        let hash = queued_hash(&club).await?;
+        let (first, second) = tokio::join!(club.confirm(&hash), club.confirm(&hash));
+        let (first, second) = (first?.message(), second?.message());
+        assert!(second.starts_with("Someone beat you to it!"));
+        assert!(second.ends_with(&first));
+        assert_eq!(club.repo.list_rotation().await?.len(), 1);
+        assert_eq!(club.repo.list_ratings().await?.len(), 2);
+        assert_eq!(club.repo.list_draws().await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_undo_restores_the_last_commit() -> Result<()> {
+        let club = club().await?;
+        club.repo.set_rotation(&["Cat".to_owned()]).await?;
+        club.resume().await?;
+        let announcement = club.confirm(&queued_hash(&club).await?).await?.message();
+        // Wait for the prefetch to let go of the lock
+        drop(club.cycle.lock().await);
+
+        let undo = club.undo().await?.unwrap();
+        // The prefetched draw's seed was announced with the commit, so it's revealed
+        let dropped = undo.dropped.as_ref().unwrap();
+        assert!(announcement.contains(&dropped.seed_hash));
+        assert!(undo.message().contains(&dropped.seed));
+        assert_eq!(club.repo.list_rotation().await?, vec!["Cat"]);
+        assert_eq!(club.repo.get_current().await?.name, "Trust");
+        assert!(club
+            .repo
+            .list_albums()
+            .await?
+            .iter()
+            .all(|album| album.played_on.is_none()));
+        assert!(club.undo().await?.is_none());
+
+        // The same album comes up again, and its draw isn't recorded twice
+        let again = club.confirm(&queued_hash(&club).await?).await?.message();
+        assert_eq!(
+            again.split(" \n ").next(),
+            announcement.split(" \n ").next()
+        );
+        assert_eq!(club.repo.list_draws().await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_undos_roll_back() -> Result<()> {
+        let club = club().await?;
+        club.resume().await?;
+        let committed = match club.confirm(&queued_hash(&club).await?).await? {
+            Next::Committed { album, .. } => album,
+            Next::Debounced(_) => unreachable!(),
+        };
+        drop(club.cycle.lock().await);
+        // Gone from the backlog, so it can't be unmarked as played
+        club.repo.remove_album(&committed).await?;
+
+        let err = club.undo().await.unwrap_err().to_string();
+        assert!(err.contains("Nothing was changed"), "{}", err);
+        assert_eq!(club.repo.get_current().await?.name, committed.name);
+        assert_eq!(
+            club.repo.list_rotation().await?,
+            vec![committed.added_by.clone()]
+        );
+        assert!(club.repo.get_state(LAST_TRANSITION).await?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_commits_roll_back() -> Result<()> {
+        let club = club().await?;
+        let gone = Album {
+            name: "Loveless".to_owned(),
+            added_by: "Bob".to_owned(),
+            row: 99,
+            ..Default::default()
+        };
+        let pending = AlbumAndLink {
+            draw: DrawRecord::draw(&new_seed(), &[(&gone, 1.0)])?,
+            album: gone,
+            link: None,
+            relaxed: Vec::new(),
+            reset_rotation: false,
+        };
+        let pending = serde_json::to_string(&pending)?;
+        club.repo.set_state(PENDING_PICK, Some(&pending)).await?;
+        club.resume().await?;
+
+        let err = club
+            .confirm(&queued_hash(&club).await?)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Nothing was changed"), "{}", err);
+        assert!(club.repo.list_rotation().await?.is_empty());
+        assert_eq!(club.repo.get_current().await?.name, "Trust");
+        assert_eq!(
+            club.repo.get_state(PENDING_PICK).await?.as_deref(),
+            Some(pending.as_str())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_relaxed_draws_reset_the_rotation_first() -> Result<()> {
+        let club = club().await?;
+        club.repo.set_rotation(&["Cat".to_owned()]).await?;
+        let syro = club.repo.list_albums().await?.remove(0);
+        let pending = AlbumAndLink {
+            draw: DrawRecord::draw(&new_seed(), &[(&syro, 1.0)])?,
+            album: syro,
+            link: None,
+            relaxed: Vec::new(),
+            reset_rotation: true,
+        };
+        let pending = serde_json::to_string(&pending)?;
+        club.repo.set_state(PENDING_PICK, Some(&pending)).await?;
+        club.resume().await?;
+
+        // The reset happens before Ann goes in, so the rotation ends up with her
+        // in it rather than empty
+        match club.confirm(&queued_hash(&club).await?).await? {
+            Next::Committed {
+                reset_rotation,
+                cleared_rotation,
+                ..
+            } => assert_eq!((reset_rotation, cleared_rotation), (true, false)),
+            Next::Debounced(_) => unreachable!(),
+        }
+        assert_eq!(club.repo.list_rotation().await?, vec!["Ann"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rerolls_are_limited() -> Result<()> {
+        let club = club().await?;
+        club.resume().await?;
+        let first = club.propose().await?;
+        let (_, proposal) = club.reroll_proposal(&first.pending.draw.seed_hash).await?;
+        let first = first.pending.draw.seed_hash;
+        assert_eq!(proposal.rerolls_left, 1);
+        assert!(club.confirm(&first).await.is_err());
+
+        let second = proposal.pending.draw.seed_hash;
+        // Each re-roll draws with the seed whose hash the proposal before it announced
+        let announced = proposal.reroll_hash.clone();
+        let (_, proposal) = club.reroll_proposal(&second).await?;
+        assert_eq!(proposal.pending.draw.seed_hash, announced);
+        assert_eq!(proposal.replaced.unwrap().seed_hash, second);
+        let third = proposal.pending.draw.seed_hash;
+        assert_eq!(proposal.rerolls_left, 0);
+        assert!(club.reroll_proposal(&third).await.is_err());
+        // The count survives a restart
+        let restarted = Club::new(club.repo.clone(), club.rules.clone());
+        restarted.resume().await?;
+        assert_eq!(restarted.propose().await?.rerolls_left, 0);
+        club.confirm(&third).await?;
+        assert_eq!(club.repo.list_draws().await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_vetoes_redraw_without_the_album() -> Result<()> {
+        let club = club().await?;
+        club.resume().await?;
+        let hash = queued_hash(&club).await?;
+        let vetoed = match club.confirm(&hash).await? {
+            Next::Committed { album, .. } => album,
+            Next::Debounced(_) => unreachable!(),
+        };
+        // Two of the three members have to vote, and only members can. Ann has
+        // submitted through the bot, so only her account votes as her
+        assert!(club.veto(&hash, "4", "Dan").await.is_err());
+        assert!(club.veto(&hash, "2", "Ann").await.is_err());
+        assert!(matches!(
+            club.veto(&hash, "1", "Annie").await?,
+            VetoOutcome::Counted {
+                votes: 1,
+                needed: 2,
+                ..
+            }
+        ));
+        assert!(club.veto(&hash, "1", "Bob").await.is_err());
+        let announced = seed_hash(&club.cycle.lock().await.next_seed.unwrap());
+        let outcome = club.veto(&hash, "2", "Bob").await?;
+        let (dropped, next_hash) = match &outcome {
+            VetoOutcome::Passed {
+                dropped: Some(dropped),
+                next_hash: Some(next_hash),
+                ..
+            } => (dropped, next_hash),
+            _ => unreachable!(),
+        };
+        // The prefetched draw used the seed announced with the commit, so it's
+        // revealed, and the replacement is drawn with a fresh one
+        assert_eq!(dropped.seed_hash, announced);
+        assert!(outcome.message("Bob").contains(&dropped.seed));
+        assert_ne!(*next_hash, announced);
+        let proposal = club.propose().await?;
+        assert_eq!(proposal.pending.draw.seed_hash, *next_hash);
+        assert!(!same_album(&proposal.pending.album, &vetoed));
+        assert!(club.veto(&hash, "3", "Cat").await.is_err());
+        assert!(club.repo.list_rotation().await?.is_empty());
+        assert_eq!(club.repo.get_current().await?.name, "Trust");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_polls_commit_the_winner() -> Result<()> {
+        let club = club().await?;
+        club.resume().await?;
+        let (poll, opened) = club.open_poll(3, chrono::Duration::hours(1), 1).await?;
+        assert!(opened);
+        // Only two albums are left to nominate
+        assert_eq!(poll.nominees.len(), 2);
+        assert!(!same_album(
+            &poll.nominees[0].album,
+            &poll.nominees[1].album
+        ));
+        assert!(!club.open_poll(3, chrono::Duration::hours(1), 1).await?.1);
+
+        club.vote(poll.hash(), "1", 1).await?;
+        club.vote(poll.hash(), "2", 0).await?;
+        club.vote(poll.hash(), "1", 0).await?;
+        club.vote(poll.hash(), "3", 1).await?;
+        let winner = &poll.nominees[0].album;
+        match club.close_poll(poll.hash()).await? {
+            Next::Committed { album, .. } => assert!(same_album(&album, winner)),
+            Next::Debounced(_) => unreachable!(),
+        }
+        assert!(club.close_poll(poll.hash()).await.is_err());
+        assert!(club.vote(poll.hash(), "4", 1).await.is_err());
+        assert_eq!(
+            club.repo.list_rotation().await?,
+            vec![winner.added_by.clone()]
+        );
+        // Both nominees' draws are on record, once each
+        let draws = club.repo.list_draws().await?;
+        assert_eq!(draws.len(), 2);
+        assert!(poll.nominees.iter().all(|pick| draws.contains(&pick.draw)));
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+use crate::albums::{today, Album, Rating};
+use crate::selection::{RuleSet, SelectionContext};
+
+use anyhow::{anyhow, Result};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The randomness behind one draw. Its hash is announced before the draw and
+/// the seed itself once the pick is revealed.
+pub type Seed = [u8; 32];
+
+pub fn new_seed() -> Seed {
+    rand::thread_rng().gen()
+}
+
+pub fn seed_hash(seed: &Seed) -> String {
+    hex::encode(Sha256::digest(seed))
+}
+
+/// The seed for the `index`th of several albums drawn together, worked out from
+/// the announced seed of the first so it's fixed in advance too.
+pub fn derive_seed(seed: &Seed, index: usize) -> Seed {
+    Sha256::new()
+        .chain_update(seed)
+        .chain_update((index as u32).to_be_bytes())
+        .finalize()
+        .into()
+}
+
+pub fn parse_seed(seed: &str) -> Result<Seed> {
+    hex::decode(seed.trim())?
+        .try_into()
+        .map_err(|_| anyhow!("The seed isn't 32 bytes"))
+}
+
+/// One album that was in the running for a draw.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Candidate {
+    pub artist: String,
+    pub album: String,
+    pub added_by: String,
+    pub weight: f64,
+}
+
+fn candidates(odds: &[(&Album, f64)]) -> Vec<Candidate> {
+    odds.iter()
+        .map(|(album, weight)| Candidate {
+            artist: album.artist.clone(),
+            album: album.name.clone(),
+            added_by: album.added_by.clone(),
+            weight: *weight,
+        })
+        .collect()
+}
+
+/// Only what the rules and weights look at, to keep records small.
+fn draw_input(album: &Album) -> Album {
+    Album {
+        name: album.name.clone(),
+        artist: album.artist.clone(),
+        genre: album.genre.clone(),
+        added_by: album.added_by.clone(),
+        row: album.row,
+        year: album.year,
+        length: album.length,
+        added_on: album.added_on.clone(),
+        ..Default::default()
+    }
+}
+
+/// What a draw's candidates were worked out from, so they can be worked out
+/// again.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DrawInputs {
+    /// The house rules, written like `SELECTION_RULES`.
+    pub rules: String,
+    /// The unplayed backlog.
+    pub albums: Vec<Album>,
+    pub rotation: Vec<String>,
+    /// As many of the latest picks as the rules look back over, newest first.
+    pub history: Vec<Album>,
+    pub today: String,
+}
+
+impl DrawInputs {
+    pub fn new(rules: &RuleSet, albums: &[Album], context: &SelectionContext) -> Self {
+        let mut rotation: Vec<String> = context.rotation.iter().cloned().collect();
+        rotation.sort();
+        DrawInputs {
+            rules: rules.spec().to_owned(),
+            albums: albums
+                .iter()
+                .filter(|album| album.played_on.is_none())
+                .map(draw_input)
+                .collect(),
+            rotation,
+            history: context
+                .history
+                .iter()
+                .take(rules.lookback())
+                .map(|rating| draw_input(&rating.album))
+                .collect(),
+            today: context.today.format("%Y-%m-%d").to_string(),
+        }
+    }
+
+    fn candidates(&self) -> Result<Vec<Candidate>> {
+        let rules = RuleSet::parse(&self.rules)?;
+        let context = SelectionContext {
+            rotation: self.rotation.iter().cloned().collect(),
+            history: self
+                .history
+                .iter()
+                .map(|album| Rating {
+                    album: album.clone(),
+                    date: None,
+                    extra: Vec::new(),
+                })
+                .collect(),
+            today: chrono::NaiveDate::parse_from_str(&self.today, "%Y-%m-%d")?,
+        };
+        Ok(candidates(&rules.candidates(&self.albums, &context)?.odds))
+    }
+}
+
+/// Everything needed to rerun a draw offline: the candidates in the order they
+/// were weighed, the seed, which one came out, and what the candidates were
+/// worked out from. Draws recorded before the inputs were kept don't have them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DrawRecord {
+    pub date: String,
+    pub seed: String,
+    pub seed_hash: String,
+    pub candidates: Vec<Candidate>,
+    pub picked: usize,
+    #[serde(default)]
+    pub inputs: Option<DrawInputs>,
+}
+
+fn sample(seed: &Seed, weights: impl Iterator<Item = f64>) -> Result<usize> {
+    let mut rng = ChaCha20Rng::from_seed(*seed);
+    Ok(WeightedIndex::new(weights)?.sample(&mut rng))
+}
+
+impl DrawRecord {
+    /// Draws one of `odds` using `seed`.
+    pub fn draw(seed: &Seed, odds: &[(&Album, f64)]) -> Result<Self> {
+        let picked = sample(seed, odds.iter().map(|(_, weight)| *weight))?;
+        Ok(DrawRecord {
+            date: today(),
+            seed: hex::encode(seed),
+            seed_hash: seed_hash(seed),
+            candidates: candidates(odds),
+            picked,
+            inputs: None,
+        })
+    }
+
+    pub fn pick(&self) -> Result<&Candidate> {
+        self.candidates
+            .get(self.picked)
+            .ok_or_else(|| anyhow!("The draw picked a candidate it doesn't have"))
+    }
+
+    /// Checks that the seed matches the hash announced for it, that the recorded
+    /// backlog and history give the recorded candidates, and that rerunning the
+    /// draw with the seed picks the same album.
+    pub fn verify(&self) -> Result<()> {
+        let seed = parse_seed(&self.seed)?;
+        if seed_hash(&seed) != self.seed_hash {
+            return Err(anyhow!("The seed doesn't match its announced hash"));
+        }
+        if let Some(inputs) = &self.inputs {
+            let rebuilt = inputs.candidates()?;
+            // Weights are compared loosely, since they've been through JSON
+            let same = rebuilt.len() == self.candidates.len()
+                && rebuilt.iter().zip(&self.candidates).all(|(a, b)| {
+                    (a.artist == b.artist && a.album == b.album && a.added_by == b.added_by)
+                        && (a.weight - b.weight).abs() < 1e-9
+                });
+            if !same {
+                return Err(anyhow!(
+                    "The recorded backlog and rules don't give the recorded candidates"
+                ));
+            }
+        }
+        let rerun = sample(&seed, self.candidates.iter().map(|c| c.weight))?;
+        if rerun != self.picked {
+            let expected = &self.candidates[rerun];
+            return Err(anyhow!(
+                "Rerunning the draw picks {} by {} instead",
+                expected.album,
+                expected.artist
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn albums() -> Vec<Album> {
+        ["Syro", "Souvlaki", "Untrue"]
+            .iter()
+            .map(|name| Album {
+                name: name.to_string(),
+                artist: "Someone".to_owned(),
+                added_by: "Ann".to_owned(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_draws_are_reproducible() -> Result<()> {
+        let albums = albums();
+        let odds: Vec<(&Album, f64)> = albums.iter().map(|album| (album, 1.0 / 3.0)).collect();
+        let seed = new_seed();
+        let draw = DrawRecord::draw(&seed, &odds)?;
+        assert_eq!(draw, DrawRecord::draw(&seed, &odds)?);
+        draw.verify()?;
+
+        let stored: DrawRecord = serde_json::from_str(&serde_json::to_string(&draw)?)?;
+        stored.verify()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_candidates_are_rebuilt_from_the_inputs() -> Result<()> {
+        let mut albums = albums();
+        albums[2].genre = String::from("Garage");
+        let context = SelectionContext {
+            history: vec![Rating {
+                album: Album {
+                    genre: String::from("Garage"),
+                    ..Default::default()
+                },
+                date: None,
+                extra: Vec::new(),
+            }],
+            ..Default::default()
+        };
+        let pick = RuleSet::default().select(&albums, &context, &new_seed())?;
+        assert_eq!(pick.draw.candidates.len(), 2);
+        let stored: DrawRecord = serde_json::from_str(&serde_json::to_string(&pick.draw)?)?;
+        stored.verify()?;
+
+        // A candidate the rules would have left out gives the draw away
+        let mut padded = stored.clone();
+        let mut extra = padded.candidates[0].clone();
+        extra.album = albums[2].name.clone();
+        padded.candidates.push(extra);
+        assert_eq!(
+            padded.verify().unwrap_err().to_string(),
+            "The recorded backlog and rules don't give the recorded candidates"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_tampered_draws_fail_verification() -> Result<()> {
+        let albums = albums();
+        let odds: Vec<(&Album, f64)> = albums.iter().map(|album| (album, 1.0 / 3.0)).collect();
+        let draw = DrawRecord::draw(&new_seed(), &odds)?;
+
+        let mut other_pick = draw.clone();
+        other_pick.picked = (draw.picked + 1) % 3;
+        assert!(other_pick.verify().is_err());
+
+        let mut other_seed = draw.clone();
+        other_seed.seed = hex::encode(new_seed());
+        assert_eq!(
+            other_seed.verify().unwrap_err().to_string(),
+            "The seed doesn't match its announced hash"
+        );
+        Ok(())
+    }
+}
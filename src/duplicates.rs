@@ -0,0 +1,167 @@
+use crate::albums::{Album, AlbumRepo, Rating};
+
+use anyhow::Result;
+
+/// Lowercases `text` and drops punctuation, so "Sgt. Pepper's" and
+/// "sgt peppers" come out the same.
+pub fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| !matches!(c, '\'' | '’'))
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Whether two albums are the same record: the same Spotify album, or the same
+/// artist and title once normalized.
+pub fn is_duplicate(a: &Album, b: &Album) -> bool {
+    if let (Some(a), Some(b)) = (&a.spotify_id, &b.spotify_id) {
+        if a == b {
+            return true;
+        }
+    }
+    normalize(&a.artist) == normalize(&b.artist) && normalize(&a.name) == normalize(&b.name)
+}
+
+fn title(album: &Album) -> String {
+    format!("{} by {}", album.name, album.artist)
+}
+
+/// Says where `album` already is, if it's a duplicate of anything in `backlog`
+/// or the ratings history. Backlog entries are pointed at with `locate`.
+pub fn find_existing(
+    album: &Album,
+    backlog: &[&Album],
+    ratings: &[Rating],
+    locate: &dyn Fn(&Album) -> String,
+) -> Option<String> {
+    if let Some(existing) = backlog
+        .iter()
+        .find(|existing| is_duplicate(album, existing))
+    {
+        let mut message = match &existing.played_on {
+            Some(played_on) => format!("{} was already played on {}", title(existing), played_on),
+            None => format!("{} is already in the backlog", title(existing)),
+        };
+        message.push_str(&format!(
+            " ({}), submitted by {}",
+            locate(existing),
+            existing.added_by
+        ));
+        if let Some(added_on) = &existing.added_on {
+            message.push_str(&format!(" on {}", added_on));
+        }
+        return Some(message);
+    }
+    ratings
+        .iter()
+        .find(|rating| is_duplicate(album, &rating.album))
+        .map(|rating| match &rating.date {
+            Some(date) => format!("{} was already played on {}", title(&rating.album), date),
+            None => format!("{} has already been played", title(&rating.album)),
+        })
+}
+
+/// Finds every unplayed album in the backlog that's in it twice or has already
+/// been played, described for the club.
+pub async fn audit<R: AlbumRepo + Sync + ?Sized>(repo: &R) -> Result<Vec<String>> {
+    let backlog = repo.list_albums().await?;
+    let ratings = repo.list_ratings().await?;
+    let mut found = Vec::new();
+    for (i, album) in backlog.iter().enumerate() {
+        if album.played_on.is_some() {
+            continue;
+        }
+        // Of two unplayed copies, only the later one is flagged
+        let others: Vec<&Album> = backlog
+            .iter()
+            .enumerate()
+            .filter(|(j, other)| other.played_on.is_some() || *j < i)
+            .map(|(_, other)| other)
+            .collect();
+        if let Some(existing) = find_existing(album, &others, &ratings, &|album| repo.locate(album))
+        {
+            found.push(format!(
+                "{} (submitted by {}): {}",
+                title(album),
+                album.added_by,
+                existing
+            ));
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn album(artist: &str, name: &str, added_by: &str) -> Album {
+        Album {
+            artist: artist.to_owned(),
+            name: name.to_owned(),
+            added_by: added_by.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_duplicates_ignore_case_and_punctuation() {
+        let a = album(
+            "The Beatles",
+            "Sgt. Pepper's Lonely Hearts Club Band",
+            "Ann",
+        );
+        let b = album("the beatles", "Sgt Peppers Lonely Hearts Club Band ", "Bob");
+        assert!(is_duplicate(&a, &b));
+        assert!(!is_duplicate(&a, &album("The Beatles", "Revolver", "Bob")));
+
+        let mut misspelt = album("Beatles", "Sergeant Pepper", "Cat");
+        assert!(!is_duplicate(&a, &misspelt));
+        misspelt.spotify_id = Some("6QaVfG1pHYl1z15ZxkvVDW".to_owned());
+        let mut known = a.clone();
+        known.spotify_id = misspelt.spotify_id.clone();
+        assert!(is_duplicate(&known, &misspelt));
+    }
+
+    #[test]
+    fn test_existing_albums_are_described() {
+        let syro = Album {
+            row: 7,
+            ..album("Aphex Twin", "Syro", "Ann")
+        };
+        let played = Rating {
+            album: album("Low", "Trust", "Cat"),
+            date: Some("2022-05-20".to_owned()),
+            extra: Vec::new(),
+        };
+        let ratings = [played];
+        let locate = |album: &Album| format!("row {}", album.row);
+        assert_eq!(
+            find_existing(
+                &album("aphex twin", "SYRO", "Bob"),
+                &[&syro],
+                &ratings,
+                &locate
+            )
+            .as_deref(),
+            Some("Syro by Aphex Twin is already in the backlog (row 7), submitted by Ann")
+        );
+        assert_eq!(
+            find_existing(&album("Low", "Trust", "Bob"), &[&syro], &ratings, &locate).as_deref(),
+            Some("Trust by Low was already played on 2022-05-20")
+        );
+        assert_eq!(
+            find_existing(
+                &album("Low", "Drums and Guns", "Bob"),
+                &[&syro],
+                &ratings,
+                &locate
+            ),
+            None
+        );
+    }
+}
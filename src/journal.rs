@@ -0,0 +1,152 @@
+use std::fmt::{Display, Formatter};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+lazy_static! {
+    static ref JOURNAL_PATH: String =
+        std::env::var("JOURNAL_PATH").unwrap_or_else(|_| String::from("club-journal.jsonl"));
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Pick,
+    Submit,
+    Edit,
+    Withdraw,
+    Reroll,
+    Undo,
+    VetoVote,
+    Veto,
+    PollOpen,
+    PollVote,
+    RotationAppend,
+    RotationClear,
+    ReviewerDraw,
+    ReviewerReset,
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let action = match self {
+            Action::Pick => "picked",
+            Action::Submit => "submitted",
+            Action::Edit => "edited",
+            Action::Withdraw => "withdrew",
+            Action::Reroll => "re-rolled",
+            Action::Undo => "undid",
+            Action::VetoVote => "voted to veto",
+            Action::Veto => "vetoed",
+            Action::PollOpen => "opened a poll",
+            Action::PollVote => "voted for",
+            Action::RotationAppend => "added to the rotation",
+            Action::RotationClear => "cleared the rotation",
+            Action::ReviewerDraw => "drew a reviewer",
+            Action::ReviewerReset => "reset the reviewers",
+        };
+        write!(f, "{}", action)
+    }
+}
+
+/// One thing that happened to the club, and who made it happen.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub at: String,
+    pub actor: String,
+    pub action: Action,
+    pub detail: String,
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.at, self.actor, self.action)?;
+        if !self.detail.is_empty() {
+            write!(f, ": {}", self.detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// An append-only log of everything done through the bot, one JSON entry per
+/// line. Entries are never rewritten, so it can settle disputes the sheet can't.
+pub struct Journal {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl Journal {
+    pub fn from_env() -> Self {
+        Self::open(JOURNAL_PATH.as_str())
+    }
+
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        Journal {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub async fn record(&self, actor: &str, action: Action, detail: &str) -> Result<()> {
+        let entry = Entry {
+            at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            actor: actor.to_owned(),
+            action,
+            detail: detail.to_owned(),
+        };
+        let _lock = self.lock.lock().await;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        Ok(())
+    }
+
+    /// The last `count` entries, oldest first.
+    pub async fn recent(&self, count: usize) -> Result<Vec<Entry>> {
+        let _lock = self.lock.lock().await;
+        let file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut entries = BufReader::new(file)
+            .lines()
+            .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect::<Result<Vec<Entry>>>()?;
+        let skip = entries.len().saturating_sub(count);
+        Ok(entries.split_off(skip))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_entries_are_appended() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("journal-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let journal = Journal::open(&path);
+        assert!(journal.recent(5).await?.is_empty());
+
+        journal.record("ann", Action::Pick, "Syro").await?;
+        journal.record("ann", Action::RotationAppend, "Ann").await?;
+        journal.record("bob", Action::ReviewerDraw, "Cat").await?;
+        let entries = journal.recent(2).await?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].action, Action::RotationAppend);
+        assert_eq!(entries[1].actor, "bob");
+        assert!(entries[1].to_string().ends_with("bob drew a reviewer: Cat"));
+        Ok(())
+    }
+}
@@ -0,0 +1,593 @@
+use crate::albums::{album_from_vec, Album, Member, Rating};
+
+use anyhow::{anyhow, Result};
+use serde_derive::Deserialize;
+
+/// Where the club's data lives in the spreadsheet. Loaded from the JSON file in
+/// `SHEET_LAYOUT_PATH` when that is set; anything the file leaves out falls back
+/// to the layout the club sheet has always had.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SheetLayout {
+    pub albums_tab: String,
+    pub rotation_tab: String,
+    pub ratings_tab: String,
+    pub members_tab: String,
+    /// Where the bot keeps a record of every draw. Written by the bot only.
+    pub draws_tab: String,
+    /// Where the bot keeps what it needs across restarts, as key and value
+    /// columns. Written by the bot only.
+    pub state_tab: String,
+    /// Where the reviewer queue lives: who is up this cycle, and the date each
+    /// of them reviewed. Written by the bot only.
+    pub reviewers_tab: String,
+    /// The rotation tab has no header row, so its columns are given by letter.
+    pub rotation_column: String,
+    /// Where the club's names lived before the members tab. Only read when the
+    /// sheet has no members tab, and then everyone listed counts as active.
+    pub names_column: String,
+    pub headers: Headers,
+}
+
+/// The header text of each column on the backlog and ratings tabs. Columns are
+/// matched case-insensitively, so they can be reordered or have others (year,
+/// notes) added around them without touching the bot.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Headers {
+    pub artist: String,
+    pub album: String,
+    pub genre: String,
+    pub added_by: String,
+    /// Backlog only: the date an album was picked. Sheets read by `sync` can do
+    /// without it, but the bot needs it to mark picks.
+    pub played: String,
+    /// Ratings only, optional: the date an album became the current one.
+    pub date: String,
+    /// Backlog only, optional: the date an album was added.
+    pub added: String,
+    /// Backlog only, optional: the submitter's pitch for the album.
+    pub pitch: String,
+    /// Backlog only, optional: the album's Spotify ID.
+    pub spotify_id: String,
+    /// Backlog only, optional: the Discord ID of whoever submitted the album.
+    pub submitter_id: String,
+    /// Optional: the release year.
+    pub year: String,
+    /// Optional: the running time in whole minutes.
+    pub length: String,
+    /// Members tab: the member's display name.
+    pub member: String,
+    /// Members tab, optional: `no`, `false` or `0` mark someone inactive.
+    pub active: String,
+    /// Members tab, optional: the date someone joined.
+    pub joined: String,
+}
+
+impl Default for SheetLayout {
+    fn default() -> Self {
+        SheetLayout {
+            albums_tab: String::from("Album Selection"),
+            rotation_tab: String::from("Rotation"),
+            ratings_tab: String::from("Ratings"),
+            members_tab: String::from("Members"),
+            draws_tab: String::from("Draws"),
+            state_tab: String::from("State"),
+            reviewers_tab: String::from("Reviewers"),
+            rotation_column: String::from("A"),
+            names_column: String::from("B"),
+            headers: Headers::default(),
+        }
+    }
+}
+
+impl Default for Headers {
+    fn default() -> Self {
+        Headers {
+            artist: String::from("Artist"),
+            album: String::from("Album"),
+            genre: String::from("Genre"),
+            added_by: String::from("Added By"),
+            played: String::from("Played"),
+            date: String::from("Date"),
+            added: String::from("Added"),
+            pitch: String::from("Pitch"),
+            spotify_id: String::from("Spotify ID"),
+            submitter_id: String::from("Submitter ID"),
+            year: String::from("Year"),
+            length: String::from("Length"),
+            member: String::from("Name"),
+            active: String::from("Active"),
+            joined: String::from("Joined"),
+        }
+    }
+}
+
+impl SheetLayout {
+    pub fn from_env() -> Result<Self> {
+        let layout: SheetLayout = match std::env::var("SHEET_LAYOUT_PATH") {
+            Ok(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+            Err(_) => SheetLayout::default(),
+        };
+        layout.validate()?;
+        Ok(layout)
+    }
+
+    /// Checks the columns given by letter, which would otherwise only fail once
+    /// the bot reads them.
+    fn validate(&self) -> Result<()> {
+        column_index(&self.rotation_column)
+            .map_err(|e| anyhow!("Bad rotation_column in the sheet layout: {}", e))?;
+        column_index(&self.names_column)
+            .map_err(|e| anyhow!("Bad names_column in the sheet layout: {}", e))?;
+        Ok(())
+    }
+
+    pub fn rotation_range(&self) -> String {
+        format!(
+            "{}!{}:{}",
+            self.rotation_tab, self.rotation_column, self.rotation_column
+        )
+    }
+
+    pub fn names_range(&self) -> String {
+        format!(
+            "{}!{}:{}",
+            self.rotation_tab, self.names_column, self.names_column
+        )
+    }
+
+    /// The header row and the current album.
+    pub fn current_range(&self) -> String {
+        format!("{}!1:2", self.ratings_tab)
+    }
+
+    /// The column headers a backlog tab is written with when the bot creates it.
+    pub fn albums_header(&self) -> Vec<String> {
+        let h = &self.headers;
+        vec![
+            h.artist.clone(),
+            h.album.clone(),
+            h.genre.clone(),
+            h.added_by.clone(),
+            h.added.clone(),
+            h.year.clone(),
+            h.length.clone(),
+            h.pitch.clone(),
+            h.spotify_id.clone(),
+            h.submitter_id.clone(),
+            h.played.clone(),
+        ]
+    }
+
+    /// The column headers a ratings tab is written with when the bot creates it.
+    pub fn ratings_header(&self) -> Vec<String> {
+        let h = &self.headers;
+        vec![
+            h.artist.clone(),
+            h.album.clone(),
+            h.genre.clone(),
+            h.added_by.clone(),
+            h.year.clone(),
+            h.length.clone(),
+            h.date.clone(),
+        ]
+    }
+
+    /// The column headers a members tab is written with when the bot creates it.
+    pub fn members_header(&self) -> Vec<String> {
+        let h = &self.headers;
+        vec![h.member.clone(), h.active.clone(), h.joined.clone()]
+    }
+
+    /// Finds the columns of the members tab from its header row.
+    pub fn member_columns(&self, header: &[String]) -> Result<MemberColumns> {
+        let h = &self.headers;
+        Ok(MemberColumns {
+            width: header.len(),
+            name: find_column(header, &h.member).ok_or_else(|| {
+                anyhow!("The {} tab has no {} column", self.members_tab, h.member)
+            })?,
+            active: find_column(header, &h.active),
+            joined: find_column(header, &h.joined),
+        })
+    }
+
+    /// Finds the columns of a backlog or ratings tab from its header row.
+    pub fn columns(&self, tab: &str, header: &[String]) -> Result<Columns> {
+        let find = |name: &str| find_column(header, name);
+        let require = |name: &str| {
+            find(name).ok_or_else(|| anyhow!("The {} tab has no {} column", tab, name))
+        };
+        let h = &self.headers;
+        Ok(Columns {
+            width: header.len(),
+            artist: require(&h.artist)?,
+            album: require(&h.album)?,
+            genre: require(&h.genre)?,
+            added_by: require(&h.added_by)?,
+            played: find(&h.played),
+            date: find(&h.date),
+            added: find(&h.added),
+            pitch: find(&h.pitch),
+            spotify_id: find(&h.spotify_id),
+            submitter_id: find(&h.submitter_id),
+            year: find(&h.year),
+            length: find(&h.length),
+        })
+    }
+
+    /// Splits a whole tab into its columns and data rows.
+    pub fn split_tab<'a>(
+        &self,
+        tab: &str,
+        values: &'a [Vec<String>],
+    ) -> Result<(Columns, &'a [Vec<String>])> {
+        let (header, rows) = values
+            .split_first()
+            .ok_or_else(|| anyhow!("The {} tab has no header row", tab))?;
+        Ok((self.columns(tab, header)?, rows))
+    }
+}
+
+fn find_column(header: &[String], name: &str) -> Option<usize> {
+    header
+        .iter()
+        .position(|cell| cell.trim().eq_ignore_ascii_case(name.trim()))
+}
+
+/// Turns a column index into its A1 letter, `0` being `A`.
+pub fn column_letter(mut index: usize) -> String {
+    let mut letters = Vec::new();
+    loop {
+        letters.push((b'A' + (index % 26) as u8) as char);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    letters.iter().rev().collect()
+}
+
+/// Turns an A1 column letter into its index, `A` being `0`. Sheets stop at
+/// three letters.
+pub fn column_index(letters: &str) -> Result<usize> {
+    let letters = letters.trim();
+    if letters.is_empty() || letters.len() > 3 || !letters.bytes().all(|b| b.is_ascii_alphabetic())
+    {
+        return Err(anyhow!("{:?} isn't a column letter", letters));
+    }
+    Ok(letters
+        .to_ascii_uppercase()
+        .bytes()
+        .fold(0, |acc, b| acc * 26 + (b - b'A') as usize + 1)
+        - 1)
+}
+
+/// Where each field sits on one tab, as found from its header row.
+#[derive(Clone, Debug)]
+pub struct Columns {
+    width: usize,
+    artist: usize,
+    album: usize,
+    genre: usize,
+    added_by: usize,
+    pub played: Option<usize>,
+    pub date: Option<usize>,
+    added: Option<usize>,
+    pitch: Option<usize>,
+    spotify_id: Option<usize>,
+    submitter_id: Option<usize>,
+    year: Option<usize>,
+    length: Option<usize>,
+}
+
+fn is_blank(row: &[String]) -> bool {
+    row.iter().all(|cell| cell.is_empty())
+}
+
+fn cell(row: &[String], i: Option<usize>) -> Option<String> {
+    i.and_then(|i| row.get(i))
+        .filter(|cell| !cell.is_empty())
+        .cloned()
+}
+
+impl Columns {
+    fn album(&self, row: &[String], i: usize) -> Result<Album> {
+        // Stops at the first missing cell so album_from_vec can say which one it was
+        let values: Vec<String> = [self.artist, self.album, self.genre, self.added_by]
+            .iter()
+            .map_while(|&column| row.get(column).cloned())
+            .collect();
+        let number = |column| cell(row, column).and_then(|cell| cell.trim().parse::<u32>().ok());
+        Ok(Album {
+            year: number(self.year).and_then(|year| u16::try_from(year).ok()),
+            length: number(self.length),
+            added_on: cell(row, self.added),
+            pitch: cell(row, self.pitch),
+            spotify_id: cell(row, self.spotify_id),
+            submitter_id: cell(row, self.submitter_id),
+            ..album_from_vec(&values, i)?
+        })
+    }
+
+    /// The columns that aren't one of the layout's, left to right.
+    fn other_columns(&self, width: usize) -> impl Iterator<Item = usize> + '_ {
+        let known = [
+            Some(self.artist),
+            Some(self.album),
+            Some(self.genre),
+            Some(self.added_by),
+            self.played,
+            self.date,
+            self.added,
+            self.pitch,
+            self.spotify_id,
+            self.submitter_id,
+            self.year,
+            self.length,
+        ];
+        (0..width).filter(move |i| !known.contains(&Some(*i)))
+    }
+
+    /// Parses the data rows of a backlog tab, skipping blank rows. `row` on each
+    /// album is its index among the data rows.
+    pub fn albums(&self, rows: &[Vec<String>]) -> Result<Vec<Album>> {
+        rows.iter()
+            .enumerate()
+            .filter(|(_, row)| !is_blank(row))
+            .map(|(i, row)| {
+                let mut album = self.album(row, i)?;
+                album.played_on = cell(row, self.played);
+                Ok(album)
+            })
+            .collect()
+    }
+
+    /// Parses the data rows of a ratings tab, newest first, skipping blank rows.
+    /// Columns the layout doesn't know about end up in `extra`.
+    pub fn ratings(&self, rows: &[Vec<String>]) -> Result<Vec<Rating>> {
+        rows.iter()
+            .enumerate()
+            .filter(|(_, row)| !is_blank(row))
+            .map(|(i, row)| {
+                Ok(Rating {
+                    album: self.album(row, i)?,
+                    date: cell(row, self.date),
+                    extra: self
+                        .other_columns(row.len())
+                        .map(|i| row[i].clone())
+                        .collect(),
+                })
+            })
+            .collect()
+    }
+
+    fn row(&self, album: &Album) -> Vec<String> {
+        let mut row = vec![String::new(); self.width];
+        row[self.artist] = album.artist.clone();
+        row[self.album] = album.name.clone();
+        row[self.genre] = album.genre.clone();
+        row[self.added_by] = album.added_by.clone();
+        if let (Some(added), Some(value)) = (self.added, &album.added_on) {
+            row[added] = value.clone();
+        }
+        if let (Some(pitch), Some(value)) = (self.pitch, &album.pitch) {
+            row[pitch] = value.clone();
+        }
+        if let (Some(spotify_id), Some(value)) = (self.spotify_id, &album.spotify_id) {
+            row[spotify_id] = value.clone();
+        }
+        if let (Some(submitter_id), Some(value)) = (self.submitter_id, &album.submitter_id) {
+            row[submitter_id] = value.clone();
+        }
+        if let (Some(year), Some(value)) = (self.year, album.year) {
+            row[year] = value.to_string();
+        }
+        if let (Some(length), Some(value)) = (self.length, album.length) {
+            row[length] = value.to_string();
+        }
+        row
+    }
+
+    pub fn backlog_row(&self, album: &Album) -> Vec<String> {
+        let mut row = self.row(album);
+        if let Some(played) = self.played {
+            row[played] = album.played_on.clone().unwrap_or_default();
+        }
+        row
+    }
+
+    /// `existing` with the layout's columns rewritten from `album`, keeping
+    /// whatever else the club put in the row.
+    pub fn edited_row(&self, existing: &[String], album: &Album) -> Vec<String> {
+        let mut row = existing.to_vec();
+        row.resize(row.len().max(self.width), String::new());
+        let others: Vec<usize> = self.other_columns(self.width).collect();
+        for (i, value) in self.backlog_row(album).into_iter().enumerate() {
+            if !others.contains(&i) {
+                row[i] = value;
+            }
+        }
+        row
+    }
+
+    pub fn rating_row(&self, rating: &Rating) -> Vec<String> {
+        let mut row = self.row(&rating.album);
+        if let Some(date) = self.date {
+            row[date] = rating.date.clone().unwrap_or_default();
+        }
+        let mut extra = rating.extra.iter();
+        for i in self.other_columns(self.width) {
+            if let Some(value) = extra.next() {
+                row[i] = value.clone();
+            }
+        }
+        // Keep anything that didn't fit rather than dropping it
+        row.extend(extra.cloned());
+        row
+    }
+}
+
+/// Where each field sits on the members tab.
+#[derive(Clone, Debug)]
+pub struct MemberColumns {
+    width: usize,
+    name: usize,
+    active: Option<usize>,
+    joined: Option<usize>,
+}
+
+fn is_active(cell: Option<&String>) -> bool {
+    !matches!(
+        cell.map(|cell| cell.trim().to_lowercase()).as_deref(),
+        Some("no" | "false" | "0" | "n")
+    )
+}
+
+impl MemberColumns {
+    /// Parses the data rows of the members tab, skipping rows without a name.
+    pub fn members(&self, rows: &[Vec<String>]) -> Vec<Member> {
+        rows.iter()
+            .filter_map(|row| {
+                let name = row.get(self.name).filter(|name| !name.is_empty())?;
+                Some(Member {
+                    name: name.clone(),
+                    active: is_active(self.active.and_then(|i| row.get(i))),
+                    joined: cell(row, self.joined),
+                })
+            })
+            .collect()
+    }
+
+    pub fn member_row(&self, member: &Member) -> Vec<String> {
+        let mut row = vec![String::new(); self.width];
+        row[self.name] = member.name.clone();
+        if let Some(active) = self.active {
+            row[active] = String::from(if member.active { "yes" } else { "no" });
+        }
+        if let Some(joined) = self.joined {
+            row[joined] = member.joined.clone().unwrap_or_default();
+        }
+        row
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn strings(cells: &[&str]) -> Vec<String> {
+        cells.iter().map(|cell| cell.to_string()).collect()
+    }
+
+    #[test]
+    fn test_columns_are_found_by_header() -> Result<()> {
+        let layout = SheetLayout::default();
+        let values = vec![
+            strings(&["Year", "added by", "Album", "Artist", "Genre", "Notes"]),
+            strings(&[
+                "1997",
+                "Ann",
+                "OK Computer",
+                "Radiohead",
+                "Rock",
+                "a classic",
+            ]),
+        ];
+        let (columns, rows) = layout.split_tab("Album Selection", &values)?;
+        let albums = columns.albums(rows)?;
+        assert_eq!(albums[0].artist, "Radiohead");
+        assert_eq!(albums[0].name, "OK Computer");
+        assert_eq!(albums[0].added_by, "Ann");
+        assert_eq!(albums[0].played_on, None);
+        assert_eq!(albums[0].year, Some(1997));
+        assert_eq!(columns.backlog_row(&albums[0])[..5], values[1][..5]);
+        let edited = Album {
+            name: "Kid A".to_owned(),
+            ..albums[0].clone()
+        };
+        let row = columns.edited_row(&values[1], &edited);
+        assert_eq!(row[2], "Kid A");
+        assert_eq!(row[5], "a classic");
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_column_is_an_error() {
+        let layout = SheetLayout::default();
+        let header = strings(&["Artist", "Album", "Added By"]);
+        let err = layout.columns("Ratings", &header).unwrap_err();
+        assert_eq!(err.to_string(), "The Ratings tab has no Genre column");
+    }
+
+    #[test]
+    fn test_ratings_keep_unknown_columns() -> Result<()> {
+        let layout = SheetLayout::default();
+        let values = vec![
+            strings(&["Artist", "Album", "Genre", "Added By", "Ann", "Date", "Bob"]),
+            strings(&["Low", "Trust", "Slowcore", "Cat", "8", "2022-05-20", "7"]),
+        ];
+        let (columns, rows) = layout.split_tab("Ratings", &values)?;
+        let ratings = columns.ratings(rows)?;
+        assert_eq!(ratings[0].date.as_deref(), Some("2022-05-20"));
+        assert_eq!(ratings[0].extra, vec!["8", "7"]);
+        assert_eq!(columns.rating_row(&ratings[0]), values[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ratings_without_a_date_column_keep_their_scores_in_place() -> Result<()> {
+        let layout = SheetLayout::default();
+        let values = vec![
+            strings(&["Artist", "Album", "Genre", "Added By", "Ann", "Bob"]),
+            strings(&["Low", "Trust", "Slowcore", "Cat", "8", "7"]),
+        ];
+        let (columns, rows) = layout.split_tab("Ratings", &values)?;
+        let mut rating = columns.ratings(rows)?.remove(0);
+        assert_eq!(rating.date, None);
+        assert_eq!(rating.extra, vec!["8", "7"]);
+        // A dated pick has nowhere to put its date, rather than pushing the scores along
+        rating.date = Some(String::from("2022-05-20"));
+        assert_eq!(columns.rating_row(&rating), values[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_members() -> Result<()> {
+        let layout = SheetLayout::default();
+        let columns = layout.member_columns(&strings(&["Joined", "Name", "Active"]))?;
+        let members = columns.members(&[
+            strings(&["2020-01-04", "Ann", "yes"]),
+            strings(&["", "Bob", "FALSE"]),
+            strings(&["", "", "yes"]),
+            strings(&["", "Cat"]),
+        ]);
+        let names: Vec<(&str, bool)> = members
+            .iter()
+            .map(|member| (member.name.as_str(), member.active))
+            .collect();
+        assert_eq!(names, vec![("Ann", true), ("Bob", false), ("Cat", true)]);
+        assert_eq!(members[0].joined.as_deref(), Some("2020-01-04"));
+        assert_eq!(columns.member_row(&members[1]), strings(&["", "Bob", "no"]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_letters() -> Result<()> {
+        for (index, letters) in [(0, "A"), (4, "E"), (25, "Z"), (26, "AA"), (701, "ZZ")] {
+            assert_eq!(column_letter(index), letters);
+            assert_eq!(column_index(letters)?, index);
+        }
+        assert_eq!(column_index(" b ")?, 1);
+        for bad in ["", "1", "A1", "É", "ABCD"] {
+            assert!(column_index(bad).is_err(), "{}", bad);
+        }
+        let layout = SheetLayout {
+            names_column: String::from("B2"),
+            ..SheetLayout::default()
+        };
+        assert!(layout.validate().is_err());
+        SheetLayout::default().validate()
+    }
+}
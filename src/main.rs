@@ -1,84 +1,1440 @@
 mod albums;
+mod backlog;
+mod club;
+mod draw;
+mod duplicates;
+mod journal;
+mod layout;
+mod permissions;
+mod selection;
 mod spotify;
+mod sqlite;
+mod sync;
 
+use std::collections::HashMap;
 use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::albums::{Album, AlbumRepo, GoogleSheetsAlbumRepo};
-use crate::spotify::Spotify;
+use crate::albums::{Album, AlbumRepo, GoogleSheetsAlbumRepo, Submitter};
+use crate::backlog::{BacklogFilter, Eligibility, Page};
+use crate::club::{Club, ClubMode, Next, Poll, Proposal, VetoOutcome};
+use crate::journal::{Action, Journal};
+use crate::layout::SheetLayout;
+use crate::permissions::Permissions;
+use crate::selection::{RuleSet, SelectionContext};
+use crate::spotify::{album_link, Spotify, SpotifyMatch};
+use crate::sqlite::SqliteAlbumRepo;
+use crate::sync::{sync, ClubSnapshot, SheetDump};
 
 use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
 use log::error;
 use serenity::async_trait;
+use serenity::builder::{CreateComponents, CreateEmbed, CreateSelectMenu};
 use serenity::client::{Client, Context, EventHandler};
 use serenity::framework::standard::{macros::group, StandardFramework};
+use serenity::http::Http;
 use serenity::model::application::command::CommandOptionType;
+use serenity::model::application::component::{ActionRowComponent, ButtonStyle, InputTextStyle};
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::application::interaction::modal::ModalSubmitInteraction;
 use serenity::model::application::interaction::{Interaction, InteractionResponseType};
 use serenity::model::gateway::GatewayIntents;
 use serenity::model::gateway::Ready;
-use serenity::model::id::GuildId;
+use serenity::model::guild::Member;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::model::user::User;
 use tokio::sync::Mutex;
 
 #[group]
 struct General;
 
-struct AlbumAndLink {
+#[derive(Clone)]
+struct AlbumHandler {
+    club: Club,
+    album_repo: Arc<BoxedAlbumRepo>,
+    rules: Arc<RuleSet>,
+    journal: Arc<Journal>,
+    permissions: Arc<Permissions>,
+    mode: ClubMode,
+    /// Submissions waiting for their submitter to pick the Spotify match, by
+    /// the nonce in their menu's id.
+    submissions: Arc<Mutex<HashMap<String, Submission>>>,
+    /// Whether the periodic backlog audit is running, since Discord can call
+    /// `ready` again on reconnecting.
+    audit_started: Arc<AtomicBool>,
+    /// Whether the poll left open by the last run has been scheduled to close,
+    /// for the same reason.
+    poll_resumed: Arc<AtomicBool>,
+}
+
+/// How `/album weights` and `/album odds` lay out the next draw's candidates.
+#[derive(Clone, Copy, PartialEq)]
+enum CandidateView {
+    /// Grouped by submitter, showing how each member's share is split.
+    BySubmitter,
+    /// Likeliest first, followed by the albums the rules leave out.
+    ByOdds,
+}
+
+/// An album from `/album submit` and what Spotify found for it.
+struct Submission {
     album: Album,
-    link: Option<String>,
+    matches: Vec<SpotifyMatch>,
+    submitted: Instant,
 }
 
-impl AlbumAndLink {
-    fn as_message(&self) -> String {
-        if let Some(link) = &self.link {
-            format!("The next album is {} \n {}", self.album, link)
-        } else {
-            format!(
-                "The next album is {} \n I had some trouble finding it on Spotify though.",
-                self.album
-            )
+const ERROR_RESPONSE_FETCH_RANDOM: &str = "Try again later!";
+const WE_HAVE_OPTIONS_FOR_A_REASON: &str = "C'mon folks, use the options for the slash command!";
+const DISCORD_MESSAGE_LIMIT: usize = 2000;
+const JOURNAL_LOG_LENGTH: usize = 20;
+
+lazy_static! {
+    /// Where the periodic backlog audit reports duplicates, if anywhere.
+    static ref AUDIT_CHANNEL_ID: Option<u64> = env::var("AUDIT_CHANNEL_ID")
+        .ok()
+        .and_then(|id| id.parse().ok());
+    static ref AUDIT_INTERVAL: Duration = Duration::from_secs(
+        env::var("AUDIT_HOURS")
+            .ok()
+            .and_then(|hours| hours.parse::<u64>().ok())
+            .unwrap_or(24)
+            // An interval of 0 panics, and one too long to count overflows
+            .clamp(1, 24 * 365)
+            * 3600
+    );
+}
+
+const NOT_ALLOWED: &str = "You're not allowed to do that.";
+const SPOTIFY_MATCHES: u32 = 5;
+/// The menu choice for adding a submission as typed.
+const NO_MATCH: &str = "none";
+/// How long a submitter has to pick the Spotify match, which is as long as
+/// Discord lets the bot edit its answer.
+const SUBMISSION_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Whether `member` can run the club's admin commands.
+fn is_admin(member: Option<&Member>) -> bool {
+    member
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.administrator())
+}
+
+/// Whether `user` is allowed to run `command`, written as `"<command> <choice>"`.
+fn is_allowed(
+    permissions: &Permissions,
+    command: &str,
+    user: &User,
+    member: Option<&Member>,
+) -> bool {
+    let roles = member
+        .map(|member| member.roles.as_slice())
+        .unwrap_or_default();
+    permissions.allows(command, user.id, roles, is_admin(member))
+}
+
+/// The choice picked for a command's first option.
+/// Commands with subcommands, like `/album`, give the subcommand's name.
+fn choice(command: &ApplicationCommandInteraction) -> &str {
+    match command.data.options.first() {
+        Some(option) if option.kind == CommandOptionType::SubCommand => &option.name,
+        option => option
+            .and_then(|option| option.value.as_ref())
+            .and_then(|value| value.as_str())
+            .unwrap_or_default(),
+    }
+}
+
+/// The value given for the command's option `name`, or its subcommand's, if any.
+fn string_option<'a>(command: &'a ApplicationCommandInteraction, name: &str) -> Option<&'a str> {
+    let options = match command.data.options.first() {
+        Some(option) if option.kind == CommandOptionType::SubCommand => &option.options,
+        _ => &command.data.options,
+    };
+    options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+}
+
+/// The name albums are submitted under: the server display name where there is one.
+fn display_name(user: &User, member: Option<&Member>) -> String {
+    match member {
+        Some(member) => member.display_name().into_owned(),
+        None => user.name.clone(),
+    }
+}
+
+/// Whether `user` can change `submitter`'s nominations: their own, found by
+/// their Discord ID, or anyone's by name for admins.
+fn may_manage(submitter: &Submitter, user: &User, member: Option<&Member>) -> bool {
+    match submitter {
+        Submitter::Id(id) => *id == user.id.to_string(),
+        Submitter::Name(_) => is_admin(member),
+    }
+}
+
+/// Writes `submitter` into a custom id, as `id:<Discord ID>` or `name:<name>`.
+fn submitter_key(submitter: &Submitter) -> String {
+    match submitter {
+        Submitter::Id(id) => format!("id:{}", id),
+        Submitter::Name(name) => format!("name:{}", name),
+    }
+}
+
+fn parse_submitter(key: &str) -> Option<Submitter> {
+    match key.split_once(':')? {
+        ("id", id) => Some(Submitter::Id(id.to_owned())),
+        ("name", name) => Some(Submitter::Name(name.to_owned())),
+        _ => None,
+    }
+}
+
+/// "your" for a member's own nominations, "Ann's" for someone else's.
+fn whose(submitter: &Submitter) -> String {
+    match submitter {
+        Submitter::Id(_) => String::from("your"),
+        Submitter::Name(name) => format!("{}'s", name),
+    }
+}
+
+/// Adds the Confirm and Reroll buttons for a proposed pick. They carry the hash
+/// of its draw's seed, so pressing them on an outdated proposal does nothing.
+fn pick_buttons<'a>(
+    components: &'a mut CreateComponents,
+    proposal: &Proposal,
+) -> &'a mut CreateComponents {
+    let seed_hash = &proposal.pending.draw.seed_hash;
+    components.create_action_row(|row| {
+        row.create_button(|button| {
+            button
+                .custom_id(format!("confirm:{}", seed_hash))
+                .label("Confirm")
+                .style(ButtonStyle::Success)
+        })
+        .create_button(|button| {
+            button
+                .custom_id(format!("reroll:{}", seed_hash))
+                .label("Reroll")
+                .style(ButtonStyle::Secondary)
+                .disabled(proposal.rerolls_left == 0)
+        })
+    })
+}
+
+fn short_name(album: &Album) -> String {
+    format!("{} by {} ({})", album.name, album.artist, album.added_by)
+}
+
+/// Adds a button for each nominee in a poll.
+fn poll_buttons<'a>(components: &'a mut CreateComponents, poll: &Poll) -> &'a mut CreateComponents {
+    components.create_action_row(|row| {
+        for i in 0..poll.nominees.len() {
+            row.create_button(|button| {
+                button
+                    .custom_id(format!("vote:{}:{}", poll.hash(), i))
+                    .label(format!("{}", i + 1))
+                    .style(ButtonStyle::Primary)
+            });
         }
+        row
+    })
+}
+
+/// The buttons that go under a reply.
+enum Buttons {
+    Pick(Box<Proposal>),
+    Poll(Box<Poll>),
+    /// The Veto button for the pick whose draw's seed has this hash.
+    Veto(String),
+}
+
+fn add_buttons<'a>(
+    components: &'a mut CreateComponents,
+    buttons: &Buttons,
+) -> &'a mut CreateComponents {
+    match buttons {
+        Buttons::Pick(proposal) => pick_buttons(components, proposal),
+        Buttons::Poll(poll) => poll_buttons(components, poll),
+        Buttons::Veto(seed_hash) => veto_button(components, seed_hash),
     }
 }
 
-#[derive(Clone)]
-struct AlbumHandler {
-    next_album: Arc<Mutex<Option<AlbumAndLink>>>,
-    album_repo: Arc<Box<dyn AlbumRepo + Send + Sync>>,
+/// Adds the Veto button under an announced pick.
+fn veto_button<'a>(
+    components: &'a mut CreateComponents,
+    seed_hash: &str,
+) -> &'a mut CreateComponents {
+    components.create_action_row(|row| {
+        row.create_button(|button| {
+            button
+                .custom_id(format!("veto:{}", seed_hash))
+                .label("Veto")
+                .style(ButtonStyle::Danger)
+        })
+    })
 }
 
-const ERROR_RESPONSE_FETCH_RANDOM: &str = "Try again later!";
-const WE_HAVE_OPTIONS_FOR_A_REASON: &str = "C'mon folks, use the options for the slash command!";
+/// The value typed into the form field `custom_id`, unless it was left blank.
+fn form_field(form: &ModalSubmitInteraction, custom_id: &str) -> Option<String> {
+    form.data
+        .components
+        .iter()
+        .flat_map(|row| &row.components)
+        .find_map(|component| match component {
+            ActionRowComponent::InputText(input) if input.custom_id == custom_id => {
+                Some(input.value.trim().to_owned())
+            }
+            _ => None,
+        })
+        .filter(|value| !value.is_empty())
+}
 
-impl AlbumHandler {
-    async fn set_next_album(&self) -> Result<()> {
-        let next_album = self.fetch_next_album().await?;
-        let mut lock = self.next_album.lock().await;
-        let _ = lock.insert(next_album);
-        Ok(())
-    }
-
-    async fn get_next_album(&self) -> Result<String> {
-        let lock = self.next_album.lock().await;
-        let album = if lock.is_some() {
-            lock.as_ref()
-                .ok_or_else(|| anyhow!("Too much rock and roll!"))?
+fn match_embed(found: &SpotifyMatch) -> CreateEmbed {
+    let mut details = vec![found.artist.clone()];
+    if let Some(year) = found.year {
+        details.push(year.to_string());
+    }
+    if let Some(tracks) = found.tracks {
+        details.push(format!("{} tracks", tracks));
+    }
+    let mut embed = CreateEmbed::default();
+    embed.title(&found.name).description(details.join(" · "));
+    if let Some(link) = &found.link {
+        embed.url(link);
+    }
+    if let Some(cover) = &found.cover {
+        embed.thumbnail(cover);
+    }
+    embed
+}
+
+/// The menu a submitter picks their album from, ending with adding it as typed.
+/// Its id is `match:<submitter>:<nonce>`, the nonce telling apart several
+/// submissions from the same member.
+fn match_menu(submitter: UserId, nonce: &str, matches: &[SpotifyMatch]) -> CreateSelectMenu {
+    let mut menu = CreateSelectMenu::default();
+    menu.custom_id(format!("match:{}:{}", submitter, nonce))
+        .placeholder("Pick the album you meant")
+        .options(|options| {
+            for (i, found) in matches.iter().enumerate() {
+                let mut label = format!("{} - {}", found.name, found.artist);
+                if let Some(year) = found.year {
+                    label.push_str(&format!(" ({})", year));
+                }
+                options.create_option(|option| {
+                    // Discord cuts labels off at 100 characters
+                    option
+                        .label(label.chars().take(100).collect::<String>())
+                        .value(i)
+                });
+            }
+            options.create_option(|option| {
+                option
+                    .label("None of these, add it as typed")
+                    .value(NO_MATCH)
+            })
+        });
+    menu
+}
+
+/// The menu for picking one of `submitter`'s nominations to edit or withdraw.
+/// Only `user`, who asked for it, can use it.
+fn submission_menu(
+    action: &str,
+    user: UserId,
+    submitter: &Submitter,
+    albums: &[Album],
+) -> CreateSelectMenu {
+    let mut menu = CreateSelectMenu::default();
+    menu.custom_id(format!("{}:{}:{}", action, user, submitter_key(submitter)))
+        .placeholder(format!("Pick the album to {}", action))
+        .options(|options| {
+            // Discord only takes 25 options
+            for album in albums.iter().take(25) {
+                let label = format!("{} - {}", album.name, album.artist);
+                options.create_option(|option| {
+                    option
+                        .label(label.chars().take(100).collect::<String>())
+                        .value(album.row)
+                });
+            }
+            options
+        });
+    menu
+}
+
+fn backlog_embed(page: &Page, filter: &BacklogFilter) -> CreateEmbed {
+    let lines: Vec<String> = page.entries.iter().map(|entry| entry.to_string()).collect();
+    let mut footer = format!(
+        "Page {} of {} · {} albums · showing {}",
+        page.number + 1,
+        page.pages,
+        page.total,
+        filter
+    );
+    if !page.relaxed.is_empty() {
+        footer.push_str(&format!(
+            " · Nothing fits every rule, so I {}",
+            page.relaxed.join(", then ")
+        ));
+    }
+    let mut embed = CreateEmbed::default();
+    embed
+        .title("The backlog")
+        .description(if lines.is_empty() {
+            String::from("Nothing in the backlog matches.")
         } else {
-            return Ok(String::from("Hold on, I'm still booting up."));
+            lines.join("\n")
+        })
+        .footer(|f| f.text(footer));
+    embed
+}
+
+/// Adds the Previous and Next buttons under a page of the backlog.
+fn backlog_buttons<'a>(
+    components: &'a mut CreateComponents,
+    page: &Page,
+    filter: &BacklogFilter,
+) -> &'a mut CreateComponents {
+    components.create_action_row(|row| {
+        row.create_button(|button| {
+            button
+                .custom_id(filter.custom_id(page.number.saturating_sub(1)))
+                .label("Previous")
+                .style(ButtonStyle::Secondary)
+                .disabled(page.number == 0)
+        });
+        row.create_button(|button| {
+            button
+                .custom_id(filter.custom_id(page.number + 1))
+                .label("Next")
+                .style(ButtonStyle::Secondary)
+                .disabled(page.is_last())
+        })
+    })
+}
+
+/// Joins lines into one message, cutting it short if Discord wouldn't take it.
+fn fit_message(lines: &[String]) -> String {
+    let mut message = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        let more = format!("...and {} more", lines.len() - i);
+        if message.len() + line.len() + more.len() + 2 > DISCORD_MESSAGE_LIMIT {
+            return message + &more;
+        }
+        message.push_str(line);
+        message.push('\n');
+    }
+    message
+}
+
+impl AlbumHandler {
+    /// Writes down what someone did. A journal that can't be written to
+    /// shouldn't stop the club, so failures are only logged.
+    async fn record(&self, actor: &str, action: Action, detail: &str) {
+        if let Err(e) = self.journal.record(actor, action, detail).await {
+            error!("Error writing to the journal {:?}", e);
+        }
+    }
+
+    /// The next draw's seed hash, if it still has to be announced before
+    /// anything can be drawn, or what went wrong committing to one.
+    async fn announce_seed(&self) -> Option<String> {
+        match self.club.announce_seed().await {
+            Ok(announcement) => announcement,
+            Err(e) => {
+                error!("Error committing to the next seed {:?}", e);
+                Some(String::from(ERROR_RESPONSE_FETCH_RANDOM))
+            }
+        }
+    }
+
+    async fn propose_next_album(&self) -> (String, Option<Buttons>) {
+        if let Some(message) = self.announce_seed().await {
+            return (message, None);
+        }
+        match self.club.propose().await {
+            Ok(proposal) => (proposal.message(), Some(Buttons::Pick(Box::new(proposal)))),
+            Err(e) => {
+                error!("Error drawing the next album {:?}", e);
+                (String::from(ERROR_RESPONSE_FETCH_RANDOM), None)
+            }
+        }
+    }
+
+    /// Journals a commit, returning the announcement and the hash for its Veto
+    /// button, or what to tell whoever tried when nothing was committed.
+    async fn record_commit(
+        &self,
+        actor: &str,
+        next: Result<Next>,
+    ) -> std::result::Result<(String, String), String> {
+        match next {
+            Ok(Next::Committed {
+                announcement,
+                album,
+                hash,
+                reset_rotation,
+                cleared_rotation,
+            }) => {
+                self.record(actor, Action::Pick, &album.to_string()).await;
+                // In the order they happened, so the journal ends with the
+                // rotation as it is
+                if reset_rotation {
+                    self.record(actor, Action::RotationClear, "").await;
+                }
+                self.record(actor, Action::RotationAppend, &album.added_by)
+                    .await;
+                if cleared_rotation {
+                    self.record(actor, Action::RotationClear, "").await;
+                }
+                Ok((announcement, hash))
+            }
+            Ok(debounced) => Err(debounced.message()),
+            Err(e) => {
+                error!("Error moving on to the next album {:?}", e);
+                Err(format!("{:#}", e))
+            }
+        }
+    }
+
+    /// Opens a poll for the next album, or shows the one that's open. A poll
+    /// past its deadline, left over from a restart, is closed instead.
+    async fn open_poll(
+        &self,
+        ctx: &Context,
+        actor: &str,
+        nominees: usize,
+        window: chrono::Duration,
+        channel: ChannelId,
+    ) -> (String, Option<Buttons>) {
+        if self.club.poll().await.is_none() {
+            if let Some(message) = self.announce_seed().await {
+                return (message, None);
+            }
+        }
+        let (poll, opened) = match self.club.open_poll(nominees, window, channel.0).await {
+            Ok(poll) => poll,
+            Err(e) => {
+                error!("Error opening a poll {:?}", e);
+                return (String::from(ERROR_RESPONSE_FETCH_RANDOM), None);
+            }
         };
-        let added_by = (&album.album.added_by).clone();
-        let s = self.clone();
+        if poll
+            .closes_at()
+            .is_ok_and(|closes_at| closes_at <= chrono::Utc::now())
+        {
+            let closed = self.club.close_poll(poll.hash()).await;
+            return match self.record_commit(actor, closed).await {
+                Ok((announcement, hash)) => (announcement, Some(Buttons::Veto(hash))),
+                Err(message) => (message, None),
+            };
+        }
+        if opened {
+            let nominees: Vec<String> = poll
+                .nominees
+                .iter()
+                .map(|nominee| nominee.album.to_string())
+                .collect();
+            self.record(actor, Action::PollOpen, &nominees.join("; "))
+                .await;
+            self.schedule_poll_close(ctx.http.clone(), &poll);
+        }
+        (poll.message(), Some(Buttons::Poll(Box::new(poll))))
+    }
+
+    /// Closes `poll` once its deadline passes and announces the winner in the
+    /// channel it was opened in.
+    fn schedule_poll_close(&self, http: Arc<Http>, poll: &Poll) {
+        let handler = self.clone();
+        let hash = poll.hash().to_owned();
+        let channel = ChannelId(poll.channel_id);
+        let wait = poll
+            .closes_at()
+            .ok()
+            .and_then(|closes_at| (closes_at - chrono::Utc::now()).to_std().ok())
+            .unwrap_or_default();
         tokio::spawn(async move {
-            s.album_repo.add_name_to_rotation(added_by).await.unwrap();
-            s.set_next_album()
+            tokio::time::sleep(wait).await;
+            let closed = handler.club.close_poll(&hash).await;
+            // Someone may have closed it already with /album next
+            let open = |poll: Option<Poll>| poll.is_some_and(|poll| poll.hash() == hash);
+            if closed.is_err() && !open(handler.club.poll().await) {
+                return;
+            }
+            let (content, buttons) = match handler.record_commit("the poll", closed).await {
+                Ok((announcement, hash)) => (announcement, Some(Buttons::Veto(hash))),
+                Err(message) => (message, None),
+            };
+            if let Err(why) = channel
+                .send_message(&http, |message| {
+                    message.content(content);
+                    if let Some(buttons) = &buttons {
+                        message.components(|components| add_buttons(components, buttons));
+                    }
+                    message
+                })
+                .await
+            {
+                error!("Cannot announce the poll's winner: {}", why);
+            }
+        });
+    }
+
+    /// Counts a vote in a poll, returning what to tell the voter.
+    async fn vote(&self, voter: &User, button: &str) -> String {
+        let (hash, choice) = match button
+            .split_once(':')
+            .and_then(|(hash, choice)| Some((hash, choice.parse::<usize>().ok()?)))
+        {
+            Some(vote) => vote,
+            None => return String::from(WE_HAVE_OPTIONS_FOR_A_REASON),
+        };
+        match self.club.vote(hash, &voter.id.to_string(), choice).await {
+            Ok(album) => {
+                self.record(&voter.name, Action::PollVote, &album.to_string())
+                    .await;
+                format!("You voted for {}.", album)
+            }
+            Err(e) => {
+                error!("Error counting a vote {:?}", e);
+                format!("{:#}", e)
+            }
+        }
+    }
+
+    async fn reroll_proposal(
+        &self,
+        actor: &str,
+        seed_hash: &str,
+    ) -> std::result::Result<Proposal, String> {
+        match self.club.reroll_proposal(seed_hash).await {
+            Ok((replaced, proposal)) => {
+                self.record(actor, Action::Reroll, &replaced.to_string())
+                    .await;
+                Ok(proposal)
+            }
+            Err(e) => {
+                error!("Error re-rolling the proposed album {:?}", e);
+                Err(format!("{:#}", e))
+            }
+        }
+    }
+
+    /// Counts a vote to veto, returning what to tell the channel.
+    async fn veto_album(
+        &self,
+        voter: &User,
+        member: Option<&Member>,
+        seed_hash: &str,
+    ) -> std::result::Result<String, String> {
+        let name = display_name(voter, member);
+        match self
+            .club
+            .veto(seed_hash, &voter.id.to_string(), &name)
+            .await
+        {
+            Ok(outcome) => {
+                let album = match &outcome {
+                    VetoOutcome::Counted { album, .. } | VetoOutcome::Passed { album, .. } => {
+                        album.to_string()
+                    }
+                };
+                self.record(&voter.name, Action::VetoVote, &album).await;
+                if matches!(outcome, VetoOutcome::Passed { .. }) {
+                    self.record(&voter.name, Action::Veto, &album).await;
+                }
+                Ok(outcome.message(&voter.name))
+            }
+            Err(e) => {
+                error!("Error counting a veto {:?}", e);
+                Err(format!("{:#}", e))
+            }
+        }
+    }
+
+    /// Handles the buttons and menus under the bot's messages: Confirm and Reroll
+    /// under a proposed pick, Veto under an announced one, the poll's votes and
+    /// the Spotify matches for a submission.
+    async fn press_button(&self, ctx: &Context, component: &MessageComponentInteraction) {
+        // Buttons are `<action>:<id>`, where the id is usually a draw's seed hash
+        let (action, id) = match component.data.custom_id.split_once(':') {
+            Some(button) => button,
+            None => return,
+        };
+        if action == "veto" {
+            self.press_veto(ctx, component, id).await;
+            return;
+        }
+        // Menus carry on what a command started, so they're held to its permission
+        let command = match action {
+            "match" => Some("album submit"),
+            "edit" => Some("album edit"),
+            "withdraw" => Some("album withdraw"),
+            _ => None,
+        };
+        if let Some(command) = command {
+            if !is_allowed(
+                &self.permissions,
+                command,
+                &component.user,
+                component.member.as_ref(),
+            ) {
+                if let Err(why) = component
+                    .create_interaction_response(&ctx.http, |response| {
+                        response
+                            .kind(InteractionResponseType::ChannelMessageWithSource)
+                            .interaction_response_data(|message| {
+                                message.content(NOT_ALLOWED).ephemeral(true)
+                            })
+                    })
+                    .await
+                {
+                    error!("Cannot respond to menu: {}", why);
+                }
+                return;
+            }
+        }
+        if action == "match" {
+            self.press_match(ctx, component, id).await;
+            return;
+        }
+        if action == "vote" {
+            self.press_vote(ctx, component, id).await;
+            return;
+        }
+        if action == "backlog" {
+            self.press_backlog(ctx, component, id).await;
+            return;
+        }
+        if action == "withdraw" {
+            self.press_withdraw(ctx, component, id).await;
+            return;
+        }
+        if action == "edit" {
+            self.press_edit(ctx, component, id).await;
+            return;
+        }
+        let allowed = is_allowed(
+            &self.permissions,
+            "album next",
+            &component.user,
+            component.member.as_ref(),
+        );
+        let response = component.create_interaction_response(&ctx.http, |response| {
+            if allowed {
+                response.kind(InteractionResponseType::DeferredUpdateMessage)
+            } else {
+                response
+                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|message| {
+                        message.content(NOT_ALLOWED).ephemeral(true)
+                    })
+            }
+        });
+        if let Err(why) = response.await {
+            error!("Cannot respond to button: {}", why);
+            return;
+        }
+        if !allowed {
+            return;
+        }
+
+        let actor = &component.user.name;
+        let update = match action {
+            // Confirming the pick opens the veto vote on it
+            "confirm" => self
+                .record_commit(actor, self.club.confirm(id).await)
+                .await
+                .map(|(announcement, hash)| (announcement, Buttons::Veto(hash))),
+            "reroll" => self
+                .reroll_proposal(actor, id)
+                .await
+                .map(|proposal| (proposal.message(), Buttons::Pick(Box::new(proposal)))),
+            _ => return,
+        };
+        let result = match update {
+            Ok((content, buttons)) => component
+                .edit_original_interaction_response(&ctx.http, |response| {
+                    response
+                        .content(content)
+                        .components(|components| add_buttons(components, &buttons))
+                })
+                .await
+                .map(|_| ()),
+            Err(message) => component
+                .create_followup_message(&ctx.http, |followup| {
+                    followup.content(message).ephemeral(true)
+                })
+                .await
+                .map(|_| ()),
+        };
+        if let Err(why) = result {
+            error!("Cannot respond to button: {}", why);
+        }
+    }
+
+    /// Any member can vote to veto, so the vote goes out to the whole channel. A
+    /// veto that passes reveals the dropped draw's seed and announces the next one.
+    async fn press_veto(
+        &self,
+        ctx: &Context,
+        component: &MessageComponentInteraction,
+        seed_hash: &str,
+    ) {
+        if let Err(why) = component
+            .create_interaction_response(&ctx.http, |response| {
+                response.kind(InteractionResponseType::DeferredUpdateMessage)
+            })
+            .await
+        {
+            error!("Cannot respond to button: {}", why);
+            return;
+        }
+        let result = match self
+            .veto_album(&component.user, component.member.as_ref(), seed_hash)
+            .await
+        {
+            Ok(content) => component
+                .create_followup_message(&ctx.http, |followup| followup.content(content))
+                .await
+                .map(|_| ()),
+            Err(message) => component
+                .create_followup_message(&ctx.http, |followup| {
+                    followup.content(message).ephemeral(true)
+                })
+                .await
+                .map(|_| ()),
+        };
+        if let Err(why) = result {
+            error!("Cannot respond to button: {}", why);
+        }
+    }
+
+    /// Votes only concern the voter, so the answer is only shown to them.
+    async fn press_vote(
+        &self,
+        ctx: &Context,
+        component: &MessageComponentInteraction,
+        button: &str,
+    ) {
+        if let Err(why) = component
+            .create_interaction_response(&ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                    .interaction_response_data(|message| message.ephemeral(true))
+            })
+            .await
+        {
+            error!("Cannot respond to button: {}", why);
+            return;
+        }
+        let content = self.vote(&component.user, button).await;
+        if let Err(why) = component
+            .edit_original_interaction_response(&ctx.http, |response| response.content(content))
+            .await
+        {
+            error!("Cannot respond to button: {}", why);
+        }
+    }
+
+    /// Opens the form for `/album submit`.
+    async fn open_submit_form(&self, ctx: &Context, command: &ApplicationCommandInteraction) {
+        let fields = [
+            ("artist", "Artist", InputTextStyle::Short, true),
+            ("album", "Album", InputTextStyle::Short, true),
+            ("genre", "Genre", InputTextStyle::Short, true),
+            (
+                "pitch",
+                "Why should we listen to it?",
+                InputTextStyle::Paragraph,
+                false,
+            ),
+        ];
+        if let Err(why) = command
+            .create_interaction_response(&ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::Modal)
+                    .interaction_response_data(|form| {
+                        form.custom_id("submit")
+                            .title("Submit an album")
+                            .components(|components| {
+                                for (id, label, style, required) in fields {
+                                    components.create_action_row(|row| {
+                                        row.create_input_text(|input| {
+                                            input
+                                                .custom_id(id)
+                                                .label(label)
+                                                .style(style)
+                                                .required(required)
+                                        })
+                                    });
+                                }
+                                components
+                            })
+                    })
+            })
+            .await
+        {
+            error!("Cannot open the submission form: {}", why);
+        }
+    }
+
+    /// Looks up the album from a filled-in `/album submit` form on Spotify and
+    /// asks the submitter which of the matches it is. Only the submitter sees
+    /// the question.
+    async fn match_submission(&self, ctx: &Context, form: &ModalSubmitInteraction) {
+        if let Err(why) = form
+            .create_interaction_response(&ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                    .interaction_response_data(|message| message.ephemeral(true))
+            })
+            .await
+        {
+            error!("Cannot respond to form: {}", why);
+            return;
+        }
+        let field = |id| form_field(form, id).unwrap_or_default();
+        let album = Album {
+            artist: field("artist"),
+            name: field("album"),
+            genre: field("genre"),
+            added_by: display_name(&form.user, form.member.as_ref()),
+            submitter_id: Some(form.user.id.to_string()),
+            pitch: form_field(form, "pitch"),
+            ..Default::default()
+        };
+        let matches = Spotify::search_albums(&album, SPOTIFY_MATCHES)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Error searching spotify {:?}", e);
+                Vec::new()
+            });
+        let content = if matches.is_empty() {
+            format!(
+                "I couldn't find {} on Spotify. Check the spelling and submit it again, or add it as typed.",
+                album
+            )
+        } else {
+            format!("Which of these is {}?", album)
+        };
+        let embeds: Vec<CreateEmbed> = matches.iter().map(match_embed).collect();
+        let nonce = hex::encode(rand::random::<[u8; 8]>());
+        let menu = match_menu(form.user.id, &nonce, &matches);
+        {
+            let mut submissions = self.submissions.lock().await;
+            // Menus nobody picked from would otherwise stay forever
+            submissions.retain(|_, submission| submission.submitted.elapsed() < SUBMISSION_TTL);
+            submissions.insert(
+                nonce,
+                Submission {
+                    album,
+                    matches,
+                    submitted: Instant::now(),
+                },
+            );
+        }
+        if let Err(why) = form
+            .edit_original_interaction_response(&ctx.http, |response| {
+                response
+                    .content(content)
+                    .set_embeds(embeds)
+                    .components(|components| {
+                        components.create_action_row(|row| row.add_select_menu(menu))
+                    })
+            })
+            .await
+        {
+            error!("Cannot respond to form: {}", why);
+        }
+    }
+
+    /// Adds a submission to the backlog once its submitter has picked the
+    /// Spotify match, taking the name, artist and year from Spotify.
+    async fn add_submission(
+        &self,
+        submitter: &User,
+        nonce: &str,
+        choice: &str,
+    ) -> std::result::Result<String, String> {
+        let submission = self
+            .submissions
+            .lock()
+            .await
+            .remove(nonce)
+            .filter(|submission| submission.submitted.elapsed() < SUBMISSION_TTL);
+        let Submission { album, matches, .. } = match submission {
+            Some(submission) => submission,
+            None => {
+                return Err(String::from(
+                    "That submission has expired. Please submit it again.",
+                ))
+            }
+        };
+        let album = match choice.parse::<usize>().ok().and_then(|i| matches.get(i)) {
+            Some(found) => found.apply(album),
+            None => album,
+        };
+        match self.album_repo.submit_album(&album).await {
+            Ok(album) => {
+                self.record(&submitter.name, Action::Submit, &album.to_string())
+                    .await;
+                let mut announcement = format!("{} submitted {}", album.added_by, album);
+                if let Some(id) = &album.spotify_id {
+                    announcement.push_str(&format!(" \n {}", album_link(id)));
+                }
+                if let Some(pitch) = &album.pitch {
+                    announcement.push_str(&format!(" \n > {}", pitch));
+                }
+                Ok(announcement)
+            }
+            Err(e) => {
+                error!("Error adding a submission {:?}", e);
+                Err(format!("I couldn't add that album: {:#}", e))
+            }
+        }
+    }
+
+    /// Handles the submitter picking which Spotify album they meant.
+    async fn press_match(&self, ctx: &Context, component: &MessageComponentInteraction, id: &str) {
+        let (owner, nonce) = id.split_once(':').unwrap_or((id, ""));
+        let mine = owner == component.user.id.to_string();
+        let response = component.create_interaction_response(&ctx.http, |response| {
+            if mine {
+                response.kind(InteractionResponseType::DeferredUpdateMessage)
+            } else {
+                response
+                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|message| {
+                        message
+                            .content("That's someone else's submission.")
+                            .ephemeral(true)
+                    })
+            }
+        });
+        if let Err(why) = response.await {
+            error!("Cannot respond to menu: {}", why);
+            return;
+        }
+        if !mine {
+            return;
+        }
+        let choice = component
+            .data
+            .values
+            .first()
+            .map(String::as_str)
+            .unwrap_or(NO_MATCH);
+        let (content, announcement) =
+            match self.add_submission(&component.user, nonce, choice).await {
+                Ok(announcement) => (String::from("Added!"), Some(announcement)),
+                Err(message) => (message, None),
+            };
+        if let Err(why) = component
+            .edit_original_interaction_response(&ctx.http, |response| {
+                response
+                    .content(content)
+                    .set_embeds(Vec::new())
+                    .components(|components| components)
+            })
+            .await
+        {
+            error!("Cannot respond to menu: {}", why);
+        }
+        if let Some(announcement) = announcement {
+            if let Err(why) = component
+                .create_followup_message(&ctx.http, |followup| followup.content(announcement))
                 .await
-                .unwrap_or_else(|_| println!("Error setting next album"))
+            {
+                error!("Cannot announce a submission: {}", why);
+            }
+        }
+    }
+
+    /// Answers `/album mine`, `/album edit` and `/album withdraw`, only to whoever
+    /// asked. Admins can name someone else's nominations with `submitter`.
+    async fn manage_submissions(&self, ctx: &Context, command: &ApplicationCommandInteraction) {
+        if let Err(why) = command
+            .create_interaction_response(&ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                    .interaction_response_data(|message| message.ephemeral(true))
+            })
+            .await
+        {
+            error!("Cannot respond to slash command: {}", why);
+            return;
+        }
+        let member = command.member.as_ref();
+        let submitter = match string_option(command, "submitter") {
+            Some(name) => Submitter::Name(name.trim().to_owned()),
+            None => Submitter::Id(command.user.id.to_string()),
+        };
+        let action = choice(command);
+        let mut menu = None;
+        let content = if action != "mine" && !may_manage(&submitter, &command.user, member) {
+            String::from(NOT_ALLOWED)
+        } else {
+            match self.album_repo.list_submissions(&submitter).await {
+                Ok(albums) if albums.is_empty() => format!(
+                    "None of {} nominations are waiting in the backlog.",
+                    whose(&submitter)
+                ),
+                Ok(albums) if action == "mine" => {
+                    let mut lines = vec![format!(
+                        "Waiting in the backlog, {} nominations:",
+                        whose(&submitter)
+                    )];
+                    lines.extend(albums.iter().map(|album| {
+                        let added = album.added_on.as_deref().unwrap_or("an unknown date");
+                        format!("{} by {}, added {}", album.name, album.artist, added)
+                    }));
+                    fit_message(&lines)
+                }
+                Ok(albums) => {
+                    menu = Some(submission_menu(
+                        action,
+                        command.user.id,
+                        &submitter,
+                        &albums,
+                    ));
+                    format!("Which of {} nominations?", whose(&submitter))
+                }
+                Err(e) => {
+                    error!("Error listing submissions {:?}", e);
+                    String::from(ERROR_RESPONSE_FETCH_RANDOM)
+                }
+            }
+        };
+        if let Err(why) = command
+            .edit_original_interaction_response(&ctx.http, |response| {
+                response.content(content);
+                if let Some(menu) = menu {
+                    response.components(|components| {
+                        components.create_action_row(|row| row.add_select_menu(menu))
+                    });
+                }
+                response
+            })
+            .await
+        {
+            error!("Cannot respond to slash command: {}", why);
+        }
+    }
+
+    /// The nomination picked from a `submission_menu`, and whose it is, as long as
+    /// the person picking is the one who opened the menu and may still change it.
+    fn picked_submission(
+        component: &MessageComponentInteraction,
+        id: &str,
+    ) -> std::result::Result<(Submitter, usize), &'static str> {
+        let (user, key) = id.split_once(':').unwrap_or_default();
+        if user != component.user.id.to_string() {
+            return Err("That's someone else's menu.");
+        }
+        let submitter = parse_submitter(key).ok_or(ERROR_RESPONSE_FETCH_RANDOM)?;
+        if !may_manage(&submitter, &component.user, component.member.as_ref()) {
+            return Err(NOT_ALLOWED);
+        }
+        let row = component
+            .data
+            .values
+            .first()
+            .and_then(|row| row.parse().ok())
+            .ok_or("Pick one of the albums.")?;
+        Ok((submitter, row))
+    }
+
+    async fn press_withdraw(
+        &self,
+        ctx: &Context,
+        component: &MessageComponentInteraction,
+        id: &str,
+    ) {
+        if let Err(why) = component
+            .create_interaction_response(&ctx.http, |response| {
+                response.kind(InteractionResponseType::DeferredUpdateMessage)
+            })
+            .await
+        {
+            error!("Cannot respond to menu: {}", why);
+            return;
+        }
+        let content = match Self::picked_submission(component, id) {
+            Ok((submitter, row)) => {
+                match self.album_repo.withdraw_submission(&submitter, row).await {
+                    Ok(album) => {
+                        self.record(&component.user.name, Action::Withdraw, &album.to_string())
+                            .await;
+                        format!("Withdrew {}.", album)
+                    }
+                    Err(e) => {
+                        error!("Error withdrawing a submission {:?}", e);
+                        format!("I couldn't withdraw that album: {:#}", e)
+                    }
+                }
+            }
+            Err(message) => String::from(message),
+        };
+        if let Err(why) = component
+            .edit_original_interaction_response(&ctx.http, |response| {
+                response
+                    .content(content)
+                    .components(|components| components)
+            })
+            .await
+        {
+            error!("Cannot respond to menu: {}", why);
+        }
+    }
+
+    /// Opens the form for editing the picked nomination, filled in with what it
+    /// says now.
+    async fn press_edit(&self, ctx: &Context, component: &MessageComponentInteraction, id: &str) {
+        let album = match Self::picked_submission(component, id) {
+            Ok((submitter, row)) => match self.album_repo.list_submissions(&submitter).await {
+                Ok(albums) => albums
+                    .into_iter()
+                    .find(|album| album.row == row)
+                    .map(|album| (submitter, album))
+                    .ok_or("That album isn't waiting in the backlog any more."),
+                Err(e) => {
+                    error!("Error listing submissions {:?}", e);
+                    Err(ERROR_RESPONSE_FETCH_RANDOM)
+                }
+            },
+            Err(message) => Err(message),
+        };
+        let response = component.create_interaction_response(&ctx.http, |response| match &album {
+            Ok((submitter, album)) => {
+                let fields = [
+                    ("artist", "Artist", InputTextStyle::Short, &album.artist),
+                    ("album", "Album", InputTextStyle::Short, &album.name),
+                    ("genre", "Genre", InputTextStyle::Short, &album.genre),
+                ];
+                let pitch = album.pitch.clone().unwrap_or_default();
+                response
+                    .kind(InteractionResponseType::Modal)
+                    .interaction_response_data(|form| {
+                        form.custom_id(format!("edit:{}:{}", album.row, submitter_key(submitter)))
+                            .title("Edit a nomination")
+                            .components(|components| {
+                                for (id, label, style, value) in fields {
+                                    components.create_action_row(|row| {
+                                        row.create_input_text(|input| {
+                                            input
+                                                .custom_id(id)
+                                                .label(label)
+                                                .style(style)
+                                                .value(value)
+                                                .required(true)
+                                        })
+                                    });
+                                }
+                                components.create_action_row(|row| {
+                                    row.create_input_text(|input| {
+                                        input
+                                            .custom_id("pitch")
+                                            .label("Why should we listen to it?")
+                                            .style(InputTextStyle::Paragraph)
+                                            .value(pitch)
+                                            .required(false)
+                                    })
+                                })
+                            })
+                    })
+            }
+            Err(message) => response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|reply| reply.content(message).ephemeral(true)),
         });
-        Ok(album.as_message())
+        if let Err(why) = response.await {
+            error!("Cannot respond to menu: {}", why);
+        }
     }
 
-    async fn get_next_reviewer(&self) -> Result<String> {
+    /// Saves a filled-in edit form, telling only the editor how it went.
+    async fn save_edit(&self, ctx: &Context, form: &ModalSubmitInteraction, id: &str) {
+        if let Err(why) = form
+            .create_interaction_response(&ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                    .interaction_response_data(|message| message.ephemeral(true))
+            })
+            .await
+        {
+            error!("Cannot respond to form: {}", why);
+            return;
+        }
+        let (row, key) = id.split_once(':').unwrap_or_default();
+        let content = match (row.parse(), parse_submitter(key)) {
+            (Ok(_), Some(submitter))
+                if !may_manage(&submitter, &form.user, form.member.as_ref()) =>
+            {
+                String::from(NOT_ALLOWED)
+            }
+            (Ok(row), Some(submitter)) => {
+                let field = |id| form_field(form, id).unwrap_or_default();
+                let edited = Album {
+                    artist: field("artist"),
+                    name: field("album"),
+                    genre: field("genre"),
+                    pitch: form_field(form, "pitch"),
+                    ..Default::default()
+                };
+                match self
+                    .album_repo
+                    .edit_submission(&submitter, row, &edited)
+                    .await
+                {
+                    Ok(album) => {
+                        self.record(&form.user.name, Action::Edit, &album.to_string())
+                            .await;
+                        format!("Saved {}.", album)
+                    }
+                    Err(e) => {
+                        error!("Error editing a submission {:?}", e);
+                        format!("I couldn't save that: {:#}", e)
+                    }
+                }
+            }
+            _ => String::from(ERROR_RESPONSE_FETCH_RANDOM),
+        };
+        if let Err(why) = form
+            .edit_original_interaction_response(&ctx.http, |response| response.content(content))
+            .await
+        {
+            error!("Cannot respond to form: {}", why);
+        }
+    }
+
+    /// Answers `/album backlog` with the first page of the browser, only to
+    /// whoever asked, so everyone can page through it on their own.
+    async fn browse_backlog(&self, ctx: &Context, command: &ApplicationCommandInteraction) {
+        if let Err(why) = command
+            .create_interaction_response(&ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                    .interaction_response_data(|message| message.ephemeral(true))
+            })
+            .await
+        {
+            error!("Cannot respond to slash command: {}", why);
+            return;
+        }
+        let filter = BacklogFilter::new(
+            string_option(command, "genre"),
+            string_option(command, "submitter"),
+            Eligibility::parse(string_option(command, "eligible").unwrap_or_default()),
+        );
+        let result = match backlog::browse(&**self.album_repo, &self.rules, &filter, 0).await {
+            Ok(page) => command
+                .edit_original_interaction_response(&ctx.http, |response| {
+                    response
+                        .add_embed(backlog_embed(&page, &filter))
+                        .components(|components| backlog_buttons(components, &page, &filter))
+                })
+                .await
+                .map(|_| ()),
+            Err(e) => {
+                error!("Error browsing the backlog {:?}", e);
+                command
+                    .edit_original_interaction_response(&ctx.http, |response| {
+                        response.content(ERROR_RESPONSE_FETCH_RANDOM)
+                    })
+                    .await
+                    .map(|_| ())
+            }
+        };
+        if let Err(why) = result {
+            error!("Cannot respond to slash command: {}", why);
+        }
+    }
+
+    /// Turns the backlog browser to another page, reading the backlog afresh.
+    async fn press_backlog(
+        &self,
+        ctx: &Context,
+        component: &MessageComponentInteraction,
+        id: &str,
+    ) {
+        let (number, filter) = match BacklogFilter::from_custom_id(id) {
+            Some(button) => button,
+            None => return,
+        };
+        if let Err(why) = component
+            .create_interaction_response(&ctx.http, |response| {
+                response.kind(InteractionResponseType::DeferredUpdateMessage)
+            })
+            .await
+        {
+            error!("Cannot respond to button: {}", why);
+            return;
+        }
+        let page = backlog::browse(&**self.album_repo, &self.rules, &filter, number).await;
+        if let Err(e) = &page {
+            error!("Error browsing the backlog {:?}", e);
+        }
+        if let Err(why) = component
+            .edit_original_interaction_response(&ctx.http, |response| match &page {
+                Ok(page) => response
+                    .content("")
+                    .set_embeds(vec![backlog_embed(page, &filter)])
+                    .components(|components| backlog_buttons(components, page, &filter)),
+                // Left as it was, the page would look like the button did nothing
+                Err(_) => response
+                    .content(ERROR_RESPONSE_FETCH_RANDOM)
+                    .set_embeds(Vec::new())
+                    .components(|components| components),
+            })
+            .await
+        {
+            error!("Cannot respond to button: {}", why);
+        }
+    }
+
+    async fn undo_next_album(&self, actor: &str) -> String {
+        match self.club.undo().await {
+            Ok(Some(undo)) => {
+                self.record(actor, Action::Undo, &undo.album.to_string())
+                    .await;
+                undo.message()
+            }
+            Ok(None) => String::from("There's nothing to undo."),
+            Err(e) => {
+                error!("Error undoing the last album {:?}", e);
+                format!("I couldn't undo the last album: {:#}", e)
+            }
+        }
+    }
+
+    async fn reroll_next_album(&self, actor: &str) -> String {
+        match self.club.reroll().await {
+            Ok((message, rerolled)) => {
+                if rerolled {
+                    self.record(actor, Action::Reroll, "").await;
+                }
+                message
+            }
+            Err(e) => {
+                error!("Error re-rolling the next album {:?}", e);
+                String::from(ERROR_RESPONSE_FETCH_RANDOM)
+            }
+        }
+    }
+
+    async fn get_next_reviewer(&self, actor: &str) -> Result<String> {
         match self.album_repo.get_random_name().await {
-            Ok(person) => Ok(format!("The next reviewer is {}", person)),
+            Ok(person) => {
+                self.record(actor, Action::ReviewerDraw, &person).await;
+                Ok(format!("The next reviewer is {}", person))
+            }
             Err(e) => {
                 error!("Error getting a random person {:?}", e);
                 Ok(String::from(ERROR_RESPONSE_FETCH_RANDOM))
@@ -86,9 +1442,12 @@ impl AlbumHandler {
         }
     }
 
-    async fn reset_reviewers(&self) -> String {
+    async fn reset_reviewers(&self, actor: &str) -> String {
         match self.album_repo.reset_reviewers().await {
-            Ok(_) => String::from("Reviewer list has been reset"),
+            Ok(_) => {
+                self.record(actor, Action::ReviewerReset, "").await;
+                String::from("Reviewer list has been reset")
+            }
             Err(e) => {
                 error!("Error resetting reviewer {:?}", e);
                 String::from(ERROR_RESPONSE_FETCH_RANDOM)
@@ -96,6 +1455,65 @@ impl AlbumHandler {
         }
     }
 
+    async fn audit_backlog(&self) -> String {
+        match duplicates::audit(&**self.album_repo).await {
+            Ok(found) if found.is_empty() => String::from("No duplicates in the backlog."),
+            Ok(found) => {
+                let mut lines = vec![String::from("Duplicates in the backlog:")];
+                lines.extend(found);
+                fit_message(&lines)
+            }
+            Err(e) => {
+                error!("Error auditing the backlog {:?}", e);
+                String::from(ERROR_RESPONSE_FETCH_RANDOM)
+            }
+        }
+    }
+
+    /// Checks the backlog for duplicates every `AUDIT_HOURS`, reporting any to
+    /// `AUDIT_CHANNEL_ID`.
+    fn schedule_audit(&self, http: Arc<Http>, channel: ChannelId) {
+        if self.audit_started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let handler = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(*AUDIT_INTERVAL);
+            loop {
+                interval.tick().await;
+                let found = match duplicates::audit(&**handler.album_repo).await {
+                    Ok(found) => found,
+                    Err(e) => {
+                        error!("Error auditing the backlog {:?}", e);
+                        continue;
+                    }
+                };
+                if found.is_empty() {
+                    continue;
+                }
+                let mut lines = vec![String::from("Found some duplicates in the backlog:")];
+                lines.extend(found);
+                if let Err(why) = channel.say(&http, fit_message(&lines)).await {
+                    error!("Cannot report duplicates: {}", why);
+                }
+            }
+        });
+    }
+
+    async fn get_journal(&self) -> String {
+        match self.journal.recent(JOURNAL_LOG_LENGTH).await {
+            Ok(entries) if entries.is_empty() => String::from("Nothing has happened yet."),
+            Ok(entries) => {
+                let lines: Vec<String> = entries.iter().map(|entry| entry.to_string()).collect();
+                fit_message(&lines)
+            }
+            Err(e) => {
+                error!("Error reading the journal {:?}", e);
+                String::from(ERROR_RESPONSE_FETCH_RANDOM)
+            }
+        }
+    }
+
     async fn get_current_album(&self) -> String {
         let album = match self.album_repo.get_current().await {
             Ok(album) => album,
@@ -108,87 +1526,229 @@ impl AlbumHandler {
             .map_err(|e| error!("Error getting spotify url {:?}", e))
             .ok();
         if let Some(Some(url)) = url {
-            return format!("The current album is {} \n {}", album, url);
+            format!("The current album is {} \n {}", album, url)
         } else {
-            return format!(
+            format!(
                 "The current album is {} \n I had trouble finding the album on spotify",
                 album
-            );
+            )
         }
     }
 
-    async fn fetch_next_album(&self) -> anyhow::Result<AlbumAndLink> {
-        let album = match self.album_repo.fetch_random_album().await {
-            Ok(album) => album,
+    async fn get_weights(&self) -> String {
+        match self.describe_candidates(CandidateView::BySubmitter).await {
+            Ok(message) => message,
+            Err(e) => {
+                error!("Error weighing the backlog {:?}", e);
+                String::from(ERROR_RESPONSE_FETCH_RANDOM)
+            }
+        }
+    }
+
+    async fn get_odds(&self) -> String {
+        match self.describe_candidates(CandidateView::ByOdds).await {
+            Ok(message) => message,
             Err(e) => {
-                error!("Error getting a random album {:?}", e);
-                return Err(anyhow::anyhow!(ERROR_RESPONSE_FETCH_RANDOM.to_owned()));
+                error!("Error working out the odds {:?}", e);
+                String::from(ERROR_RESPONSE_FETCH_RANDOM)
+            }
+        }
+    }
+
+    /// Runs the same draw as `/album next` without picking anything, and
+    /// says what could come out of it, in the order `view` asks for.
+    async fn describe_candidates(&self, view: CandidateView) -> Result<String> {
+        let albums = self.album_repo.list_albums().await?;
+        let context = SelectionContext::load(&**self.album_repo).await?;
+        let mut candidates = self.rules.candidates(&albums, &context)?;
+        let heading = match view {
+            CandidateView::BySubmitter => {
+                candidates.odds.sort_by(|(a, a_odds), (b, b_odds)| {
+                    a.added_by
+                        .to_lowercase()
+                        .cmp(&b.added_by.to_lowercase())
+                        .then(b_odds.total_cmp(a_odds))
+                });
+                "Everyone gets an equal share of the draw, split between their albums by how long they've waited:"
+            }
+            CandidateView::ByOdds => {
+                candidates
+                    .odds
+                    .sort_by(|(_, a_odds), (_, b_odds)| b_odds.total_cmp(a_odds));
+                "Odds for the next draw:"
             }
         };
-        let url = Spotify::fetch_album_link(&album)
-            .await
-            .map_err(|e| error!("Error getting spotify url {:?}", e))
-            .ok();
-        match url {
-            Some(link) => Ok(AlbumAndLink { album, link }),
-            None => Ok(AlbumAndLink { album, link: None }),
+        let mut lines = vec![String::from(heading)];
+        if !candidates.relaxed.is_empty() {
+            lines.push(format!(
+                "Nothing fits every rule right now, so I {}.",
+                candidates.relaxed.join(", then ")
+            ));
+        }
+        lines.extend(
+            candidates
+                .odds
+                .iter()
+                .map(|(album, odds)| format!("{:.1}% {}", odds * 100.0, short_name(album))),
+        );
+        if view == CandidateView::ByOdds && !candidates.excluded.is_empty() {
+            lines.push(String::from("Left out:"));
+            lines.extend(
+                candidates
+                    .excluded
+                    .iter()
+                    .map(|(album, rule)| format!("{}: {}", short_name(album), rule)),
+            );
         }
+        Ok(fit_message(&lines))
     }
 }
 
 #[async_trait]
 impl EventHandler for AlbumHandler {
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::MessageComponent(component) = &interaction {
+            self.press_button(&ctx, component).await;
+            return;
+        }
+        if let Interaction::ModalSubmit(form) = &interaction {
+            let edit = match form.data.custom_id.split_once(':') {
+                Some(("edit", id)) => Some(id),
+                _ => None,
+            };
+            // Permissions can change while the form is open
+            let command = if edit.is_some() {
+                "album edit"
+            } else {
+                "album submit"
+            };
+            if !is_allowed(&self.permissions, command, &form.user, form.member.as_ref()) {
+                if let Err(why) = form
+                    .create_interaction_response(&ctx.http, |response| {
+                        response
+                            .kind(InteractionResponseType::ChannelMessageWithSource)
+                            .interaction_response_data(|message| {
+                                message.content(NOT_ALLOWED).ephemeral(true)
+                            })
+                    })
+                    .await
+                {
+                    error!("Cannot respond to form: {}", why);
+                }
+                return;
+            }
+            match edit {
+                Some(id) => self.save_edit(&ctx, form, id).await,
+                None => self.match_submission(&ctx, form).await,
+            }
+            return;
+        }
         if let Interaction::ApplicationCommand(command) = interaction {
+            let name = format!("{} {}", command.data.name, choice(&command));
+            if !is_allowed(
+                &self.permissions,
+                &name,
+                &command.user,
+                command.member.as_ref(),
+            ) {
+                if let Err(why) = command
+                    .create_interaction_response(&ctx.http, |response| {
+                        response
+                            .kind(InteractionResponseType::ChannelMessageWithSource)
+                            .interaction_response_data(|message| {
+                                message.content(NOT_ALLOWED).ephemeral(true)
+                            })
+                    })
+                    .await
+                {
+                    error!("Cannot respond to slash command: {}", why);
+                }
+                return;
+            }
+            // The form is the answer to /album submit, so it can't wait for a deferral
+            if name == "album submit" {
+                self.open_submit_form(&ctx, &command).await;
+                return;
+            }
+            if name == "album backlog" {
+                self.browse_backlog(&ctx, &command).await;
+                return;
+            }
+            // Nominations are only shown to whoever asked
+            if ["album mine", "album edit", "album withdraw"].contains(&name.as_str()) {
+                self.manage_submissions(&ctx, &command).await;
+                return;
+            }
+            // Drawing an album takes a few round trips to the backend, more than
+            // Discord waits for a reply, so say we're on it and edit the answer in
+            if let Err(why) = command
+                .create_interaction_response(&ctx.http, |response| {
+                    response.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                })
+                .await
+            {
+                error!("Cannot respond to slash command: {}", why);
+                return;
+            }
+            let mut buttons = None;
             let content = match command.data.name.as_str() {
                 "album" => {
-                    let result = match command.data.options.get(0) {
-                        Some(option) => {
-                            match option
-                                .value
-                                .clone()
-                                .unwrap_or_else(|| {
-                                    serde_json::Value::String(String::from("Error getting command"))
-                                })
-                                .as_str()
-                                .unwrap()
-                            {
-                                "next" => self.get_next_album().await.unwrap(),
-                                "current" => self.get_current_album().await,
-                                e => {
-                                    error!("Got command {:?}", e);
-                                    WE_HAVE_OPTIONS_FOR_A_REASON.to_owned()
-                                }
+                    let result = match command.data.options.first() {
+                        Some(_) => match choice(&command) {
+                            "next" => {
+                                let (message, next) = match self.mode {
+                                    ClubMode::Random => self.propose_next_album().await,
+                                    ClubMode::Vote { nominees, window } => {
+                                        self.open_poll(
+                                            &ctx,
+                                            &command.user.name,
+                                            nominees,
+                                            window,
+                                            command.channel_id,
+                                        )
+                                        .await
+                                    }
+                                };
+                                buttons = next;
+                                message
                             }
-                        }
-                        None => WE_HAVE_OPTIONS_FOR_A_REASON.to_owned(),
-                    };
-                    result
-                }
-                "reviewer" => {
-                    let result = match command.data.options.get(0) {
-                        Some(option) => {
-                            match option.value.as_ref().unwrap().as_str().unwrap().as_ref() {
-                                "next" => self
-                                    .get_next_reviewer()
-                                    .await
-                                    .unwrap_or_else(|_| String::from(WE_HAVE_OPTIONS_FOR_A_REASON)),
-                                "reset" => self.reset_reviewers().await,
-                                _ => String::from(WE_HAVE_OPTIONS_FOR_A_REASON),
+                            "current" => self.get_current_album().await,
+                            "weights" => self.get_weights().await,
+                            "odds" => self.get_odds().await,
+                            "reroll" => self.reroll_next_album(&command.user.name).await,
+                            "undo" => self.undo_next_album(&command.user.name).await,
+                            e => {
+                                error!("Got command {:?}", e);
+                                WE_HAVE_OPTIONS_FOR_A_REASON.to_owned()
                             }
-                        }
-                        None => String::from(WE_HAVE_OPTIONS_FOR_A_REASON),
+                        },
+                        None => WE_HAVE_OPTIONS_FOR_A_REASON.to_owned(),
                     };
                     result
                 }
+                "reviewer" => match choice(&command) {
+                    "next" => self
+                        .get_next_reviewer(&command.user.name)
+                        .await
+                        .unwrap_or_else(|_| String::from(WE_HAVE_OPTIONS_FOR_A_REASON)),
+                    "reset" => self.reset_reviewers(&command.user.name).await,
+                    _ => String::from(WE_HAVE_OPTIONS_FOR_A_REASON),
+                },
+                "club" => match choice(&command) {
+                    "log" => self.get_journal().await,
+                    "audit" => self.audit_backlog().await,
+                    _ => String::from(WE_HAVE_OPTIONS_FOR_A_REASON),
+                },
                 _ => String::from("Go home, you're drunk :("),
             };
 
             if let Err(why) = command
-                .create_interaction_response(&ctx.http, |response| {
+                .edit_original_interaction_response(&ctx.http, |response| {
+                    response.content(content);
+                    if let Some(buttons) = &buttons {
+                        response.components(|components| add_buttons(components, buttons));
+                    }
                     response
-                        .kind(InteractionResponseType::ChannelMessageWithSource)
-                        .interaction_response_data(|message| message.content(content))
                 })
                 .await
             {
@@ -226,36 +1786,226 @@ impl EventHandler for AlbumHandler {
                 .create_application_command(|command| {
                     command
                         .name("album")
-                        .description("A slash command for getting the next or current album")
+                        .description("A slash command for getting the next or current album");
+                    for (name, description) in [
+                        ("next", "Get the next one"),
+                        ("current", "Get the current one"),
+                        ("submit", "Submit an album"),
+                        ("weights", "Show the draw weights"),
+                        ("odds", "Show the odds for the next draw"),
+                        ("reroll", "Re-roll the next one (admins only)"),
+                        ("undo", "Undo the last one (admins only)"),
+                    ] {
+                        command.create_option(|option| {
+                            option
+                                .name(name)
+                                .description(description)
+                                .kind(CommandOptionType::SubCommand)
+                        });
+                    }
+                    command.create_option(|option| {
+                        option
+                            .name("backlog")
+                            .description("Browse the backlog")
+                            .kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|option| {
+                                option
+                                    .name("genre")
+                                    .description("Only albums in this genre")
+                                    .kind(CommandOptionType::String)
+                                    .required(false)
+                            })
+                            .create_sub_option(|option| {
+                                option
+                                    .name("submitter")
+                                    .description("Only albums from this submitter")
+                                    .kind(CommandOptionType::String)
+                                    .required(false)
+                            })
+                            .create_sub_option(|option| {
+                                option
+                                    .name("eligible")
+                                    .description("Only albums that can or can't be drawn next")
+                                    .kind(CommandOptionType::String)
+                                    .required(false)
+                                    .add_string_choice("Can be drawn", "yes")
+                                    .add_string_choice("Can't be drawn", "no")
+                            })
+                    });
+                    for (name, description) in [
+                        ("mine", "Show my nominations"),
+                        ("edit", "Edit one of my nominations"),
+                        ("withdraw", "Withdraw one of my nominations"),
+                    ] {
+                        command.create_option(|option| {
+                            option
+                                .name(name)
+                                .description(description)
+                                .kind(CommandOptionType::SubCommand)
+                                .create_sub_option(|option| {
+                                    option
+                                        .name("submitter")
+                                        .description("Someone else's instead (admins only)")
+                                        .kind(CommandOptionType::String)
+                                        .required(false)
+                                })
+                        });
+                    }
+                    command
+                })
+                .create_application_command(|command| {
+                    command
+                        .name("club")
+                        .description("A slash command for looking back at what the club has done")
                         .create_option(|option| {
                             option
                                 .name("command")
-                                .description("What action you want to take for albums")
+                                .description("What you want to see")
                                 .kind(CommandOptionType::String)
                                 .required(true)
-                                .add_string_choice("Get the next one", "next")
-                                .add_string_choice("Get the current one", "current")
+                                .add_string_choice("Show the latest actions", "log")
+                                .add_string_choice("Check the backlog for duplicates", "audit")
                         })
                 })
         })
         .await;
+
+        if let Some(channel) = *AUDIT_CHANNEL_ID {
+            self.schedule_audit(ctx.http.clone(), ChannelId(channel));
+        }
+
+        // A poll opened before a restart still needs closing, once
+        if !self.poll_resumed.swap(true, Ordering::SeqCst) {
+            if let Some(poll) = self.club.poll().await {
+                self.schedule_poll_close(ctx.http.clone(), &poll);
+            }
+        }
+    }
+}
+
+type BoxedAlbumRepo = Box<dyn AlbumRepo + Send + Sync>;
+
+/// Opens a backend from a spec: `sheets`, `sqlite` (using `SQLITE_PATH`) or
+/// `sqlite:<path>`.
+async fn album_repo_from_spec(spec: &str) -> Result<BoxedAlbumRepo> {
+    match spec.split_once(':') {
+        Some(("sqlite", path)) => Ok(Box::new(SqliteAlbumRepo::open(path)?)),
+        _ => match spec {
+            "sheets" => Ok(Box::new(GoogleSheetsAlbumRepo::default().await?)),
+            "sqlite" => Ok(Box::new(SqliteAlbumRepo::default().await?)),
+            other => Err(anyhow!("Unknown backend {}", other)),
+        },
+    }
+}
+
+/// Picks the storage backend from `ALBUM_REPO`, defaulting to the Google Sheet.
+async fn album_repo_from_env() -> Result<BoxedAlbumRepo> {
+    let backend = env::var("ALBUM_REPO").unwrap_or_else(|_| String::from("sheets"));
+    album_repo_from_spec(&backend).await
+}
+
+/// Like `album_repo_from_spec`, but also accepts `dump:<path>` for a spreadsheet
+/// dump on disk.
+async fn snapshot_from_spec(spec: &str) -> Result<ClubSnapshot> {
+    if let Some(path) = spec.strip_prefix("dump:") {
+        let dump: SheetDump = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        ClubSnapshot::from_dump(&dump, &SheetLayout::from_env()?)
+    } else {
+        let repo = album_repo_from_spec(spec).await?;
+        ClubSnapshot::from_repo(repo.as_ref()).await
     }
 }
 
+/// `sync <from> <to> [--dry-run]`
+async fn run_sync(args: &[String]) -> Result<()> {
+    let (from, to) = match args {
+        [from, to, ..] => (from, to),
+        _ => return Err(anyhow!("Usage: sync <from> <to> [--dry-run]")),
+    };
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let snapshot = snapshot_from_spec(from).await?;
+    let repo = album_repo_from_spec(to).await?;
+    let report = sync(&snapshot, repo.as_ref(), dry_run).await?;
+    print!("{}", report);
+    if report.is_empty() {
+        println!("Already in sync");
+    } else if dry_run {
+        println!("Dry run, nothing was written");
+    }
+    Ok(())
+}
+
+/// `dump <from> <path>`
+async fn run_dump(args: &[String]) -> Result<()> {
+    let (from, path) = match args {
+        [from, path] => (from, path),
+        _ => return Err(anyhow!("Usage: dump <from> <path>")),
+    };
+    let snapshot = snapshot_from_spec(from).await?;
+    let dump = snapshot.to_dump(&SheetLayout::from_env()?)?;
+    std::fs::write(path, serde_json::to_string_pretty(&dump)?)?;
+    Ok(())
+}
+
+/// `verify <from>`
+async fn run_verify(args: &[String]) -> Result<()> {
+    let from = match args {
+        [from] => from,
+        _ => return Err(anyhow!("Usage: verify <from>")),
+    };
+    let repo = album_repo_from_spec(from).await?;
+    let mut failed = 0;
+    for draw in repo.list_draws().await? {
+        let pick = match draw.pick() {
+            Ok(pick) => format!("{} by {}", pick.album, pick.artist),
+            Err(_) => String::from("nothing"),
+        };
+        match draw.verify() {
+            Ok(()) => println!("{} {}: ok", draw.date, pick),
+            Err(e) => {
+                failed += 1;
+                println!("{} {}: {}", draw.date, pick, e);
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!("{} draw(s) failed verification", failed));
+    }
+    Ok(())
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
     env_logger::init();
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("sync") => return run_sync(&args[1..]).await,
+        Some("dump") => return run_dump(&args[1..]).await,
+        Some("verify") => return run_verify(&args[1..]).await,
+        _ => {}
+    }
+
     let framework = StandardFramework::new()
         .configure(|c| c.prefix("~")) // set the bot's prefix to "~"
         .group(&GENERAL_GROUP);
 
     // Login with a bot token from the environment
     let token = env::var("DISCORD_TOKEN").expect("token");
+    let album_repo = Arc::new(album_repo_from_env().await?);
+    album_repo.check().await?;
+    let rules = Arc::new(RuleSet::from_env()?);
     let handler = AlbumHandler {
-        album_repo: Arc::new(Box::new(GoogleSheetsAlbumRepo::default().await.unwrap())),
-        next_album: Arc::new(Mutex::new(None)),
+        club: Club::new(album_repo.clone(), rules.clone()),
+        album_repo,
+        rules,
+        journal: Arc::new(Journal::from_env()),
+        permissions: Arc::new(Permissions::from_env()?),
+        mode: ClubMode::from_env()?,
+        submissions: Arc::default(),
+        audit_started: Arc::default(),
+        poll_resumed: Arc::default(),
     };
-    handler.set_next_album().await?;
+    handler.club.resume().await?;
 
     let mut client = Client::builder(token, GatewayIntents::empty())
         .event_handler(handler)
@@ -269,3 +2019,18 @@ async fn main() -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_long_messages_are_cut_short() {
+        let lines: Vec<String> = (0..500).map(|i| format!("line {}", i)).collect();
+        let message = fit_message(&lines);
+        assert!(message.len() <= DISCORD_MESSAGE_LIMIT);
+        assert!(message.starts_with("line 0\nline 1\n"));
+        assert!(message.ends_with("more"));
+        assert_eq!(fit_message(&lines[..2]), "line 0\nline 1\n");
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde_derive::Deserialize;
+use serenity::model::id::{RoleId, UserId};
+
+/// Commands that change the club's data, as `"<command> <choice>"`. Anything
+/// else only reads and is open to everyone.
+pub const GUARDED_COMMANDS: &[&str] = &[
+    "album next",
+    "album submit",
+    "album edit",
+    "album withdraw",
+    "album reroll",
+    "album undo",
+    "reviewer next",
+    "reviewer reset",
+];
+
+/// Guarded commands open to everyone unless the permissions file says
+/// otherwise. They only touch the member's own nominations.
+const OPEN_BY_DEFAULT: &[&str] = &["album submit", "album edit", "album withdraw"];
+
+/// The roles and users allowed to run one command, or everyone.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Allowed {
+    pub everyone: bool,
+    pub roles: Vec<RoleId>,
+    pub users: Vec<UserId>,
+}
+
+/// Who can run each guarded command, read from the JSON file at
+/// `PERMISSIONS_PATH` when that is set. Admins can always run everything.
+/// Commands the file leaves out keep their defaults: submitting, editing and
+/// withdrawing nominations are open, the rest are admins only.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Permissions {
+    #[serde(flatten)]
+    commands: HashMap<String, Allowed>,
+}
+
+impl Permissions {
+    pub fn from_env() -> Result<Self> {
+        match std::env::var("PERMISSIONS_PATH") {
+            Ok(path) => Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?),
+            Err(_) => Ok(Permissions::default()),
+        }
+    }
+
+    /// Whether `user`, holding `roles`, can run `command`, written as
+    /// `"<command> <choice>"`.
+    pub fn allows(&self, command: &str, user: UserId, roles: &[RoleId], admin: bool) -> bool {
+        if admin || !GUARDED_COMMANDS.contains(&command) {
+            return true;
+        }
+        match self.commands.get(command) {
+            Some(allowed) => {
+                allowed.everyone
+                    || allowed.users.contains(&user)
+                    || roles.iter().any(|role| allowed.roles.contains(role))
+            }
+            None => OPEN_BY_DEFAULT.contains(&command),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allow_lists() -> Result<()> {
+        let permissions: Permissions = serde_json::from_str(
+            r#"{
+                "album next": { "roles": ["10"] },
+                "album undo": { "users": [20] },
+                "reviewer next": { "everyone": true },
+                "album submit": {}
+            }"#,
+        )?;
+        let (member, mod_role) = (UserId(1), RoleId(10));
+        assert!(permissions.allows("album next", member, &[mod_role], false));
+        assert!(!permissions.allows("album next", member, &[], false));
+        assert!(permissions.allows("album next", member, &[], true));
+        assert!(permissions.allows("album undo", UserId(20), &[], false));
+        assert!(!permissions.allows("album reroll", member, &[mod_role], false));
+        assert!(permissions.allows("reviewer next", member, &[], false));
+        // Left out of the file, so admins only, or open for a member's own nominations
+        assert!(!permissions.allows("reviewer reset", member, &[], false));
+        assert!(permissions.allows("album withdraw", member, &[], false));
+        assert!(!permissions.allows("album submit", member, &[], false));
+        assert!(permissions.allows("album current", member, &[], false));
+        Ok(())
+    }
+}
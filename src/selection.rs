@@ -0,0 +1,616 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::albums::{Album, AlbumRepo, Rating};
+use crate::draw::{DrawInputs, DrawRecord, Seed};
+
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+
+/// What the rules get to look at besides the album itself.
+#[derive(Clone, Debug, Default)]
+pub struct SelectionContext {
+    pub rotation: HashSet<String>,
+    /// The ratings history, newest (the current album) first.
+    pub history: Vec<Rating>,
+    /// The day of the draw, for weighing albums by how long they've waited.
+    pub today: NaiveDate,
+}
+
+impl SelectionContext {
+    pub async fn load<R: AlbumRepo + Sync + ?Sized>(repo: &R) -> Result<Self> {
+        Ok(SelectionContext {
+            rotation: repo.list_rotation().await?.into_iter().collect(),
+            history: repo.list_ratings().await?,
+            today: chrono::Local::now().date_naive(),
+        })
+    }
+
+    fn recent(&self, picks: usize) -> impl Iterator<Item = &Album> {
+        self.history.iter().take(picks).map(|rating| &rating.album)
+    }
+}
+
+/// One of the club's house rules for which albums may be drawn.
+pub trait SelectionRule: Send + Sync {
+    /// A short description, used when saying why an album was excluded.
+    fn name(&self) -> String;
+
+    fn allows(&self, album: &Album, context: &SelectionContext) -> bool;
+
+    /// How many of the latest picks the rule looks at.
+    fn lookback(&self) -> usize {
+        0
+    }
+
+    /// What to tell the club when nothing fit and this rule was set aside.
+    fn relaxed_message(&self) -> String {
+        format!("dropped the {} rule", self.name())
+    }
+
+    /// Whether setting this rule aside means the rotation starts over once the
+    /// pick is committed.
+    fn resets_rotation(&self) -> bool {
+        false
+    }
+}
+
+/// Whether two names are the same, ignoring case and surrounding spaces.
+pub(crate) fn same(a: &str, b: &str) -> bool {
+    a.trim().to_lowercase() == b.trim().to_lowercase()
+}
+
+/// No album from anyone already in the current rotation.
+pub struct Rotation;
+
+impl SelectionRule for Rotation {
+    fn name(&self) -> String {
+        String::from("rotation")
+    }
+
+    fn allows(&self, album: &Album, context: &SelectionContext) -> bool {
+        !context
+            .rotation
+            .iter()
+            .any(|name| same(name, &album.added_by))
+    }
+
+    fn relaxed_message(&self) -> String {
+        String::from("reset the rotation")
+    }
+
+    fn resets_rotation(&self) -> bool {
+        true
+    }
+}
+
+/// No album from anyone who submitted one of the last `picks` albums.
+pub struct SubmitterCooldown(pub usize);
+
+impl SelectionRule for SubmitterCooldown {
+    fn name(&self) -> String {
+        match self.0 {
+            1 => String::from("last-submitter"),
+            n => format!("submitter cooldown ({} picks)", n),
+        }
+    }
+
+    fn allows(&self, album: &Album, context: &SelectionContext) -> bool {
+        !context
+            .recent(self.0)
+            .any(|recent| same(&recent.added_by, &album.added_by))
+    }
+
+    fn lookback(&self) -> usize {
+        self.0
+    }
+}
+
+/// No album in the genre of one of the last `picks` albums.
+pub struct GenreCooldown(pub usize);
+
+impl SelectionRule for GenreCooldown {
+    fn name(&self) -> String {
+        match self.0 {
+            1 => String::from("last-genre"),
+            n => format!("genre cooldown ({} picks)", n),
+        }
+    }
+
+    fn allows(&self, album: &Album, context: &SelectionContext) -> bool {
+        !context
+            .recent(self.0)
+            .any(|recent| same(&recent.genre, &album.genre))
+    }
+
+    fn lookback(&self) -> usize {
+        self.0
+    }
+}
+
+/// No album by the artist of one of the last `picks` albums.
+pub struct ArtistCooldown(pub usize);
+
+impl SelectionRule for ArtistCooldown {
+    fn name(&self) -> String {
+        format!("artist cooldown ({} picks)", self.0)
+    }
+
+    fn allows(&self, album: &Album, context: &SelectionContext) -> bool {
+        !context
+            .recent(self.0)
+            .any(|recent| same(&recent.artist, &album.artist))
+    }
+
+    fn lookback(&self) -> usize {
+        self.0
+    }
+}
+
+/// No album from a decade that already has `max` of the last `picks` albums.
+/// Albums without a year are always allowed.
+pub struct DecadeBalance {
+    pub picks: usize,
+    pub max: usize,
+}
+
+impl SelectionRule for DecadeBalance {
+    fn name(&self) -> String {
+        format!("decade balance ({} per {} picks)", self.max, self.picks)
+    }
+
+    fn allows(&self, album: &Album, context: &SelectionContext) -> bool {
+        let decade = match album.year {
+            Some(year) => year / 10,
+            None => return true,
+        };
+        context
+            .recent(self.picks)
+            .filter(|recent| recent.year.map(|year| year / 10) == Some(decade))
+            .count()
+            < self.max
+    }
+
+    fn lookback(&self) -> usize {
+        self.picks
+    }
+}
+
+/// No album longer than this many minutes. Albums without a length are always
+/// allowed.
+pub struct MaxLength(pub u32);
+
+impl SelectionRule for MaxLength {
+    fn name(&self) -> String {
+        format!("max length ({} minutes)", self.0)
+    }
+
+    fn allows(&self, album: &Album, _context: &SelectionContext) -> bool {
+        album.length.is_none_or(|length| length <= self.0)
+    }
+}
+
+/// No album from anyone who already submitted `max` of the last `picks` albums.
+pub struct MemberQuota {
+    pub picks: usize,
+    pub max: usize,
+}
+
+impl SelectionRule for MemberQuota {
+    fn name(&self) -> String {
+        format!("member quota ({} per {} picks)", self.max, self.picks)
+    }
+
+    fn allows(&self, album: &Album, context: &SelectionContext) -> bool {
+        context
+            .recent(self.picks)
+            .filter(|recent| same(&recent.added_by, &album.added_by))
+            .count()
+            < self.max
+    }
+
+    fn lookback(&self) -> usize {
+        self.picks
+    }
+}
+
+fn added_on(album: &Album) -> Option<NaiveDate> {
+    let added_on = album.added_on.as_deref()?;
+    NaiveDate::parse_from_str(added_on.trim(), "%Y-%m-%d").ok()
+}
+
+/// Works out each album's chance of being drawn. Every submitter gets an equal
+/// share, split between their albums by how many days each has waited in the
+/// backlog. Albums with no added date predate the bot keeping track, so they
+/// count as the oldest.
+pub fn weigh(albums: &[&Album], today: NaiveDate) -> Vec<f64> {
+    let oldest = albums
+        .iter()
+        .filter_map(|album| added_on(album))
+        .min()
+        .unwrap_or(today);
+    let waited: Vec<f64> = albums
+        .iter()
+        .map(|album| {
+            let days = (today - added_on(album).unwrap_or(oldest)).num_days();
+            (days.max(0) + 1) as f64
+        })
+        .collect();
+    let submitter = |album: &Album| album.added_by.trim().to_lowercase();
+    let mut totals: HashMap<String, f64> = HashMap::new();
+    for (album, waited) in albums.iter().zip(&waited) {
+        *totals.entry(submitter(album)).or_default() += waited;
+    }
+    let submitters = totals.len() as f64;
+    albums
+        .iter()
+        .zip(&waited)
+        .map(|(album, waited)| waited / totals[&submitter(album)] / submitters)
+        .collect()
+}
+
+/// The albums that can be drawn right now, along with any rules that had to be
+/// set aside to find them.
+pub struct Candidates<'a> {
+    /// Each album and its chance of being drawn.
+    pub odds: Vec<(&'a Album, f64)>,
+    /// Each unplayed album that can't be drawn, and the rule keeping it out.
+    pub excluded: Vec<(&'a Album, String)>,
+    pub relaxed: Vec<String>,
+    pub reset_rotation: bool,
+}
+
+/// A drawn album, along with any rules that had to be set aside to draw it.
+#[derive(Clone, Debug)]
+pub struct Pick {
+    pub album: Album,
+    /// What was relaxed, in the order it was relaxed.
+    pub relaxed: Vec<String>,
+    pub reset_rotation: bool,
+    pub draw: DrawRecord,
+}
+
+/// The rules a club draws under. Played albums are never drawn. When nothing
+/// satisfies every rule, rules are set aside one at a time starting from the
+/// last one, so list the ones you'd least like to bend first.
+pub struct RuleSet {
+    rules: Vec<Box<dyn SelectionRule>>,
+    /// The rules as written, kept with each draw so it can be checked later.
+    spec: String,
+}
+
+impl Default for RuleSet {
+    /// The classic club rules: nobody already in the rotation, not the last
+    /// submitter, and not the last genre.
+    fn default() -> Self {
+        RuleSet {
+            spec: String::from("rotation,submitter_cooldown=1,genre_cooldown=1"),
+            rules: vec![
+                Box::new(Rotation),
+                Box::new(SubmitterCooldown(1)),
+                Box::new(GenreCooldown(1)),
+            ],
+        }
+    }
+}
+
+fn parse_numbers<T: std::str::FromStr>(
+    rule: &str,
+    args: Option<&str>,
+    count: usize,
+) -> Result<Vec<T>> {
+    let numbers = args
+        .ok_or_else(|| anyhow!("The {} rule needs a value", rule))?
+        .split(':')
+        .map(|n| n.trim().parse::<T>())
+        .collect::<Result<Vec<T>, _>>()
+        .map_err(|_| anyhow!("The {} rule takes whole numbers", rule))?;
+    if numbers.len() != count {
+        return Err(anyhow!("The {} rule takes {} number(s)", rule, count));
+    }
+    Ok(numbers)
+}
+
+impl RuleSet {
+    /// Reads the rules from `SELECTION_RULES`, or the classic rules without it.
+    pub fn from_env() -> Result<Self> {
+        match std::env::var("SELECTION_RULES") {
+            Ok(rules) => Self::parse(&rules),
+            Err(_) => Ok(RuleSet::default()),
+        }
+    }
+
+    /// Parses a comma-separated list of rules, like
+    /// `rotation,submitter_cooldown=1,genre_cooldown=3,decade_balance=10:4`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut rules: Vec<Box<dyn SelectionRule>> = Vec::new();
+        for rule in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+        {
+            let (name, args) = match rule.split_once('=') {
+                Some((name, args)) => (name.trim(), Some(args)),
+                None => (rule, None),
+            };
+            let numbers = |count| parse_numbers::<usize>(name, args, count);
+            rules.push(match name {
+                "rotation" => Box::new(Rotation),
+                "submitter_cooldown" => Box::new(SubmitterCooldown(numbers(1)?[0])),
+                "genre_cooldown" => Box::new(GenreCooldown(numbers(1)?[0])),
+                "artist_cooldown" => Box::new(ArtistCooldown(numbers(1)?[0])),
+                "decade_balance" => {
+                    let n = numbers(2)?;
+                    Box::new(DecadeBalance {
+                        picks: n[0],
+                        max: n[1],
+                    })
+                }
+                "max_length" => Box::new(MaxLength(parse_numbers(name, args, 1)?[0])),
+                "member_quota" => {
+                    let n = numbers(2)?;
+                    Box::new(MemberQuota {
+                        picks: n[0],
+                        max: n[1],
+                    })
+                }
+                other => return Err(anyhow!("Unknown selection rule {}", other)),
+            });
+        }
+        Ok(RuleSet {
+            rules,
+            spec: spec.trim().to_owned(),
+        })
+    }
+
+    pub fn spec(&self) -> &str {
+        &self.spec
+    }
+
+    /// How many of the latest picks any of the rules looks at.
+    pub fn lookback(&self) -> usize {
+        self.rules
+            .iter()
+            .map(|rule| rule.lookback())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Finds the albums that can be drawn under these rules and weighs them.
+    pub fn candidates<'a>(
+        &self,
+        albums: &'a [Album],
+        context: &SelectionContext,
+    ) -> Result<Candidates<'a>> {
+        for kept in (0..=self.rules.len()).rev() {
+            let (rules, relaxed) = self.rules.split_at(kept);
+            let mut filtered_albums: Vec<&Album> = Vec::new();
+            let mut excluded = Vec::new();
+            for album in albums.iter().filter(|album| album.played_on.is_none()) {
+                match rules.iter().find(|r| !r.allows(album, context)) {
+                    Some(rule) => excluded.push((album, rule.name())),
+                    None => filtered_albums.push(album),
+                }
+            }
+            if filtered_albums.is_empty() {
+                continue;
+            }
+            let weights = weigh(&filtered_albums, context.today);
+            return Ok(Candidates {
+                odds: filtered_albums.into_iter().zip(weights).collect(),
+                excluded,
+                relaxed: relaxed.iter().rev().map(|r| r.relaxed_message()).collect(),
+                reset_rotation: relaxed.iter().any(|r| r.resets_rotation()),
+            });
+        }
+        Err(anyhow!("There are no unplayed albums left in the backlog"))
+    }
+
+    /// Draws an album from the backlog under these rules, using `seed`.
+    pub fn select(
+        &self,
+        albums: &[Album],
+        context: &SelectionContext,
+        seed: &Seed,
+    ) -> Result<Pick> {
+        let candidates = self.candidates(albums, context)?;
+        let mut draw = DrawRecord::draw(seed, &candidates.odds)?;
+        draw.inputs = Some(DrawInputs::new(self, albums, context));
+        Ok(Pick {
+            album: candidates.odds[draw.picked].0.to_owned(),
+            relaxed: candidates.relaxed,
+            reset_rotation: candidates.reset_rotation,
+            draw,
+        })
+    }
+
+    /// Loads the backlog and context from `repo` and draws from it.
+    pub async fn draw<R: AlbumRepo + Sync + ?Sized>(&self, repo: &R, seed: &Seed) -> Result<Pick> {
+        let albums = repo.list_albums().await?;
+        let context = SelectionContext::load(repo).await?;
+        self.select(&albums, &context, seed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::draw::new_seed;
+
+    fn album(name: &str, genre: &str, added_by: &str) -> Album {
+        Album {
+            name: name.to_owned(),
+            artist: format!("{} artist", name),
+            genre: genre.to_owned(),
+            added_by: added_by.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn context(rotation: &[&str], history: Vec<Album>) -> SelectionContext {
+        SelectionContext {
+            rotation: rotation.iter().map(|name| name.to_string()).collect(),
+            history: history
+                .into_iter()
+                .map(|album| Rating {
+                    album,
+                    date: None,
+                    extra: Vec::new(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rules_are_relaxed_in_order() -> Result<()> {
+        let rules = RuleSet::default();
+        let context = context(&["Bob"], vec![album("Z", "Jazz", "Ann")]);
+        let pick = |albums: Vec<Album>| rules.select(&albums, &context, &new_seed());
+
+        let picked = pick(vec![album("A", "Rock", "Cat"), album("B", "Jazz", "Cat")])?;
+        assert_eq!(picked.album.name, "A");
+        assert!(picked.relaxed.is_empty());
+
+        let picked = pick(vec![album("B", "Jazz", "Cat"), album("C", "Rock", "Ann")])?;
+        assert_eq!(picked.album.name, "B");
+        assert_eq!(picked.relaxed, vec!["dropped the last-genre rule"]);
+
+        let picked = pick(vec![album("C", "Rock", "Ann"), album("D", "Rock", "Bob")])?;
+        assert_eq!(picked.album.name, "C");
+        assert_eq!(picked.relaxed.len(), 2);
+        assert!(!picked.reset_rotation);
+
+        let picked = pick(vec![album("D", "Rock", "Bob")])?;
+        assert_eq!(picked.relaxed.last().unwrap(), "reset the rotation");
+        assert!(picked.reset_rotation);
+
+        let mut played = album("E", "Rock", "Cat");
+        played.played_on = Some("2022-01-01".to_owned());
+        assert!(pick(vec![played]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_candidates_say_why_albums_are_excluded() -> Result<()> {
+        let rules = RuleSet::default();
+        let context = context(&["bob "], vec![album("Z", "Jazz", "Ann")]);
+        let mut played = album("E", "Rock", "Cat");
+        played.played_on = Some("2022-01-01".to_owned());
+        let albums = vec![
+            album("A", "Rock", "Cat"),
+            album("B", "Rock", "Bob"),
+            album("C", "Rock", "Ann"),
+            album("D", "Jazz", "Cat"),
+            played,
+        ];
+        let candidates = rules.candidates(&albums, &context)?;
+        assert_eq!(candidates.odds.len(), 1);
+        assert_eq!(candidates.odds[0].0.name, "A");
+        let excluded: Vec<(&str, &str)> = candidates
+            .excluded
+            .iter()
+            .map(|(album, rule)| (album.name.as_str(), rule.as_str()))
+            .collect();
+        assert_eq!(
+            excluded,
+            vec![
+                ("B", "rotation"),
+                ("C", "last-submitter"),
+                ("D", "last-genre")
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_rules() -> Result<()> {
+        let rules = RuleSet::parse(
+            "rotation, genre_cooldown=3, artist_cooldown=10, decade_balance=10:3, \
+            max_length=60, member_quota=6:2",
+        )?;
+        let names: Vec<String> = rules.rules.iter().map(|r| r.name()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "rotation",
+                "genre cooldown (3 picks)",
+                "artist cooldown (10 picks)",
+                "decade balance (3 per 10 picks)",
+                "max length (60 minutes)",
+                "member quota (2 per 6 picks)",
+            ]
+        );
+        assert!(RuleSet::parse("genre_cooldown").is_err());
+        assert!(RuleSet::parse("decade_balance=10").is_err());
+        assert!(RuleSet::parse("coin_flip").is_err());
+        // Too long to be a length, rather than wrapping around to a short one
+        assert!(RuleSet::parse("max_length=4294967356").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cooldowns_and_quotas() {
+        let history = vec![
+            album("A", "Rock", "Ann"),
+            album("B", "Jazz", "Bob"),
+            album("C", "Folk", "Ann"),
+        ];
+        let context = context(&[], history);
+
+        assert!(!GenreCooldown(2).allows(&album("X", "jazz", "Cat"), &context));
+        assert!(GenreCooldown(2).allows(&album("X", "Folk", "Cat"), &context));
+        assert!(!SubmitterCooldown(3).allows(&album("X", "Pop", "ann"), &context));
+        let mut same_artist = album("X", "Pop", "Cat");
+        same_artist.artist = "B artist".to_owned();
+        assert!(!ArtistCooldown(2).allows(&same_artist, &context));
+        assert!(ArtistCooldown(1).allows(&same_artist, &context));
+        assert!(!MemberQuota { picks: 3, max: 2 }.allows(&album("X", "Pop", "Ann"), &context));
+        assert!(MemberQuota { picks: 3, max: 2 }.allows(&album("X", "Pop", "Bob"), &context));
+    }
+
+    #[test]
+    fn test_submitters_get_equal_odds() {
+        let albums = [
+            album("A", "Rock", "Ann"),
+            album("B", "Rock", "Ann"),
+            album("C", "Rock", "Ann"),
+            album("D", "Rock", "Bob"),
+        ];
+        let odds = weigh(&albums.iter().collect::<Vec<_>>(), NaiveDate::default());
+        assert_eq!(odds, vec![1.0 / 6.0, 1.0 / 6.0, 1.0 / 6.0, 0.5]);
+    }
+
+    #[test]
+    fn test_older_albums_weigh_more() {
+        let today = NaiveDate::from_ymd_opt(2022, 6, 11).unwrap();
+        let mut albums = [
+            album("A", "Rock", "Ann"),
+            album("B", "Rock", "Ann"),
+            album("C", "Rock", "Ann"),
+        ];
+        albums[0].added_on = Some("2022-06-01".to_owned());
+        albums[1].added_on = Some("2022-06-11".to_owned());
+        let odds = weigh(&albums.iter().collect::<Vec<_>>(), today);
+        // 11 days, 1 day, and as old as the oldest dated album
+        assert_eq!(odds, vec![11.0 / 23.0, 1.0 / 23.0, 11.0 / 23.0]);
+    }
+
+    #[test]
+    fn test_decades_and_length() {
+        let mut nineties = album("A", "Rock", "Ann");
+        nineties.year = Some(1994);
+        let context = context(&[], vec![nineties.clone(), nineties.clone()]);
+        let balance = DecadeBalance { picks: 5, max: 2 };
+
+        let mut candidate = album("X", "Pop", "Cat");
+        assert!(balance.allows(&candidate, &context));
+        candidate.year = Some(1999);
+        assert!(!balance.allows(&candidate, &context));
+        candidate.year = Some(2001);
+        assert!(balance.allows(&candidate, &context));
+
+        candidate.length = Some(75);
+        assert!(!MaxLength(60).allows(&candidate, &context));
+        assert!(MaxLength(80).allows(&candidate, &context));
+    }
+}
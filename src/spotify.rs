@@ -3,6 +3,7 @@ use crate::albums::Album;
 use anyhow::{anyhow, Result};
 
 use rspotify::model::search::SearchResult;
+use rspotify::model::SimplifiedAlbum;
 use rspotify::{
     model::{Country, Market, SearchType},
     prelude::*,
@@ -11,52 +12,181 @@ use rspotify::{
 
 pub struct Spotify {}
 
+/// An album on Spotify that a submission might be.
+#[derive(Clone, Debug)]
+pub struct SpotifyMatch {
+    pub id: String,
+    pub name: String,
+    pub artist: String,
+    pub year: Option<u16>,
+    pub tracks: Option<u32>,
+    pub cover: Option<String>,
+    pub link: Option<String>,
+}
+
+impl SpotifyMatch {
+    /// `album` corrected to this match: Spotify's name and artist, its year if
+    /// it has one, and its ID.
+    pub fn apply(&self, album: Album) -> Album {
+        Album {
+            name: self.name.clone(),
+            artist: self.artist.clone(),
+            year: self.year.or(album.year),
+            spotify_id: Some(self.id.clone()),
+            ..album
+        }
+    }
+}
+
+/// The year of a Spotify release date, which can be `1994`, `1994-02` or
+/// `1994-02-01` depending on how precise it is.
+fn release_year(date: &str) -> Option<u16> {
+    date.get(..4).and_then(|year| year.parse().ok())
+}
+
 fn album_to_query(album: &Album) -> String {
     format!("{} {}", album.name, album.artist)
 }
 
+pub fn album_link(id: &str) -> String {
+    format!("https://open.spotify.com/album/{}", id)
+}
+
 impl Spotify {
-    pub async fn fetch_album_link(album: &Album) -> Result<Option<String>> {
+    async fn client() -> Result<ClientCredsSpotify> {
         let creds =
             Credentials::from_env().ok_or_else(|| anyhow!("Unable to get Spotify creds"))?;
         let spotify = ClientCredsSpotify::new(creds);
-
         spotify.request_token().await?;
+        Ok(spotify)
+    }
 
+    async fn search(
+        spotify: &ClientCredsSpotify,
+        album: &Album,
+        limit: u32,
+    ) -> Result<Vec<SimplifiedAlbum>> {
         let result = spotify
             .search(
                 &album_to_query(album),
                 SearchType::Album,
                 Some(Market::Country(Country::UnitedStates)),
                 None,
-                Some(1),
+                Some(limit),
                 None,
             )
             .await?;
         match result {
-            SearchResult::Albums(page) => {
-                if page.items.is_empty() {
-                    Ok(None)
-                } else {
-                    return Ok(Some(
-                        page.items[0]
-                            .to_owned()
-                            .external_urls
-                            .get("spotify")
-                            .ok_or_else(|| anyhow!("Error getting spotify url"))?
-                            .to_owned(),
-                    ));
-                }
-            }
-            _ => Ok(None),
+            SearchResult::Albums(page) => Ok(page.items),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    pub async fn fetch_album_link(album: &Album) -> Result<Option<String>> {
+        if let Some(id) = &album.spotify_id {
+            return Ok(Some(album_link(id)));
+        }
+        let spotify = Self::client().await?;
+        match Self::search(&spotify, album, 1).await?.first() {
+            Some(found) => Ok(Some(
+                found
+                    .external_urls
+                    .get("spotify")
+                    .ok_or_else(|| anyhow!("Error getting spotify url"))?
+                    .to_owned(),
+            )),
+            None => Ok(None),
         }
     }
+
+    /// The top `limit` albums on Spotify for what someone typed in, best first.
+    pub async fn search_albums(album: &Album, limit: u32) -> Result<Vec<SpotifyMatch>> {
+        let spotify = Self::client().await?;
+        let found = Self::search(&spotify, album, limit).await?;
+        let ids: Vec<_> = found.iter().filter_map(|album| album.id.clone()).collect();
+        // Search results don't say how many tracks an album has
+        let full = if ids.is_empty() {
+            Vec::new()
+        } else {
+            spotify.albums(ids).await?
+        };
+        Ok(found
+            .into_iter()
+            .filter_map(|album| {
+                let id = album.id?;
+                let tracks = full
+                    .iter()
+                    .find(|full| full.id == id)
+                    .map(|full| full.tracks.total);
+                Some(SpotifyMatch {
+                    id: id.id().to_owned(),
+                    name: album.name,
+                    artist: album
+                        .artists
+                        .iter()
+                        .map(|artist| artist.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    year: album.release_date.as_deref().and_then(release_year),
+                    tracks,
+                    cover: album.images.first().map(|image| image.url.clone()),
+                    link: album.external_urls.get("spotify").cloned(),
+                })
+            })
+            .collect())
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_release_years_are_read_at_any_precision() {
+        assert_eq!(release_year("1994-02-01"), Some(1994));
+        assert_eq!(release_year("1994-02"), Some(1994));
+        assert_eq!(release_year("1994"), Some(1994));
+        assert_eq!(release_year("94"), None);
+        assert_eq!(release_year("n/a?"), None);
+    }
+
+    #[test]
+    fn test_matches_correct_the_submission() {
+        let submitted = Album {
+            name: "souvlaki".to_owned(),
+            artist: "slowdive".to_owned(),
+            genre: "Shoegaze".to_owned(),
+            added_by: "Ann".to_owned(),
+            submitter_id: Some("1".to_owned()),
+            year: Some(1993),
+            pitch: Some("Trust me".to_owned()),
+            ..Default::default()
+        };
+        let mut found = SpotifyMatch {
+            id: "abc".to_owned(),
+            name: "Souvlaki".to_owned(),
+            artist: "Slowdive".to_owned(),
+            year: Some(1994),
+            tracks: Some(10),
+            cover: None,
+            link: None,
+        };
+        let album = found.apply(submitted.clone());
+        assert_eq!(
+            (album.name.as_str(), album.artist.as_str()),
+            ("Souvlaki", "Slowdive")
+        );
+        assert_eq!(album.year, Some(1994));
+        assert_eq!(album.spotify_id.as_deref(), Some("abc"));
+        assert_eq!(album.genre, submitted.genre);
+        assert_eq!(album.submitter_id, submitted.submitter_id);
+        assert_eq!(album.pitch, submitted.pitch);
+
+        // A match without a year keeps whatever was submitted
+        found.year = None;
+        assert_eq!(found.apply(submitted).year, Some(1993));
+    }
+
     #[tokio::test]
     #[allow(dead_code)]
     async fn test_getting_rotation() -> Result<()> {
@@ -66,6 +196,7 @@ mod test {
             genre: "Something".to_owned(),
             added_by: "Accident".to_owned(),
             row: 1,
+            ..Default::default()
         };
         println!("{:?}", Spotify::fetch_album_link(&album).await?);
         Ok(())
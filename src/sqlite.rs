@@ -0,0 +1,787 @@
+use std::collections::HashSet;
+
+use crate::albums::{same_album, today, Album, AlbumRepo, Member, Rating};
+use crate::draw::DrawRecord;
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use rand::seq::SliceRandom;
+use rusqlite::{params, Connection, OptionalExtension};
+use serenity::async_trait;
+use tokio::sync::Mutex;
+
+lazy_static! {
+    static ref SQLITE_PATH: String =
+        std::env::var("SQLITE_PATH").unwrap_or_else(|_| String::from("album-club.db"));
+}
+
+/// Schema migrations, applied in order. The index of a migration plus one is the
+/// `user_version` the database ends up at once it has been run, so never edit or
+/// reorder an entry that has shipped; append a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: backlog, members, rotation, ratings and the reviewer queue
+    "CREATE TABLE albums (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        artist TEXT NOT NULL,
+        name TEXT NOT NULL,
+        genre TEXT NOT NULL,
+        added_by TEXT NOT NULL
+    );
+    CREATE TABLE members (
+        name TEXT PRIMARY KEY
+    );
+    CREATE TABLE rotation (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL
+    );
+    CREATE TABLE ratings (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        artist TEXT NOT NULL,
+        name TEXT NOT NULL,
+        genre TEXT NOT NULL,
+        added_by TEXT NOT NULL
+    );
+    CREATE TABLE reviewers (
+        name TEXT PRIMARY KEY
+    );",
+    // 2: keep the rest of a ratings row (scores, notes) as a JSON array
+    "ALTER TABLE ratings ADD COLUMN extra TEXT NOT NULL DEFAULT '[]';",
+    // 3: picked albums stay in the backlog, flagged with the date they were played
+    "ALTER TABLE albums ADD COLUMN played_on TEXT;",
+    // 4: the date each album was picked
+    "ALTER TABLE ratings ADD COLUMN date TEXT;",
+    // 5: members can go inactive without losing their history
+    "ALTER TABLE members ADD COLUMN active INTEGER NOT NULL DEFAULT 1;
+    ALTER TABLE members ADD COLUMN joined TEXT;",
+    // 6: release year and running time, for the selection rules
+    "ALTER TABLE albums ADD COLUMN year INTEGER;
+    ALTER TABLE albums ADD COLUMN length INTEGER;
+    ALTER TABLE ratings ADD COLUMN year INTEGER;
+    ALTER TABLE ratings ADD COLUMN length INTEGER;",
+    // 7: when each album went into the backlog, for weighing the draw
+    "ALTER TABLE albums ADD COLUMN added_on TEXT;",
+    // 8: a record of every draw, seed included, so it can be rerun
+    "CREATE TABLE draws (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        date TEXT NOT NULL,
+        seed_hash TEXT NOT NULL,
+        record TEXT NOT NULL
+    );",
+    // 9: whatever the bot keeps across restarts, like the queued pick
+    "CREATE TABLE state (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );",
+    // 10: reviewers stay in the queue for the rest of the cycle once they've gone
+    "ALTER TABLE reviewers ADD COLUMN reviewed_on TEXT;",
+    // 11: what submitters wrote when they put an album forward
+    "ALTER TABLE albums ADD COLUMN pitch TEXT;",
+    // 12: the album on Spotify, confirmed when it was submitted
+    "ALTER TABLE albums ADD COLUMN spotify_id TEXT;",
+    // 13: who submitted each album on Discord, so only they can change it
+    "ALTER TABLE albums ADD COLUMN submitter_id TEXT;",
+];
+
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(anyhow!(
+            "Database is at schema version {} but this build only knows {}",
+            version,
+            MIGRATIONS.len()
+        ));
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn album_from_row(row: &rusqlite::Row) -> rusqlite::Result<Album> {
+    let id: i64 = row.get(0)?;
+    Ok(Album {
+        artist: row.get(1)?,
+        name: row.get(2)?,
+        genre: row.get(3)?,
+        added_by: row.get(4)?,
+        year: row.get(5)?,
+        length: row.get(6)?,
+        row: id as usize,
+        ..Default::default()
+    })
+}
+
+pub struct SqliteAlbumRepo {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteAlbumRepo {
+    pub async fn default() -> Result<Self> {
+        Self::open(SQLITE_PATH.as_str())
+    }
+
+    pub fn open(path: &str) -> Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    fn from_connection(mut conn: Connection) -> Result<Self> {
+        migrate(&mut conn)?;
+        Ok(SqliteAlbumRepo {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn current(conn: &Connection) -> Result<Album> {
+        conn.query_row(
+            "SELECT id, artist, name, genre, added_by, year, length
+            FROM ratings ORDER BY id DESC LIMIT 1",
+            [],
+            album_from_row,
+        )
+        .optional()?
+        .ok_or_else(|| anyhow!("Unable to get current album"))
+    }
+
+    fn albums(conn: &Connection) -> Result<Vec<Album>> {
+        let mut stmt = conn.prepare(
+            "SELECT id, artist, name, genre, added_by, year, length, played_on, added_on, pitch,
+            spotify_id, submitter_id FROM albums",
+        )?;
+        let albums = stmt
+            .query_map([], |row| {
+                Ok(Album {
+                    played_on: row.get(7)?,
+                    added_on: row.get(8)?,
+                    pitch: row.get(9)?,
+                    spotify_id: row.get(10)?,
+                    submitter_id: row.get(11)?,
+                    ..album_from_row(row)?
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<Album>>>()?;
+        Ok(albums)
+    }
+
+    fn strings(conn: &Connection, sql: &str) -> Result<Vec<String>> {
+        let mut stmt = conn.prepare(sql)?;
+        let strings = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(strings)
+    }
+
+    fn names(conn: &Connection) -> Result<HashSet<String>> {
+        Ok(HashSet::from_iter(Self::strings(
+            conn,
+            "SELECT name FROM members WHERE active",
+        )?))
+    }
+
+    fn rotation(conn: &Connection) -> Result<HashSet<String>> {
+        Ok(HashSet::from_iter(Self::strings(
+            conn,
+            "SELECT name FROM rotation",
+        )?))
+    }
+
+    /// Who hasn't reviewed yet this cycle.
+    fn reviewers(conn: &Connection) -> Result<Vec<String>> {
+        Self::strings(conn, "SELECT name FROM reviewers WHERE reviewed_on IS NULL")
+    }
+
+    /// Starts a new reviewer cycle.
+    fn fill_reviewers(conn: &Connection) -> Result<()> {
+        conn.execute("DELETE FROM reviewers", [])?;
+        let current_album = Self::current(conn)?;
+        for name in Self::names(conn)? {
+            if name != current_album.added_by {
+                conn.execute(
+                    "INSERT OR IGNORE INTO reviewers (name) VALUES (?1)",
+                    params![name],
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AlbumRepo for SqliteAlbumRepo {
+    async fn add_name_to_rotation(&self, name: String) -> Result<bool> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        tx.execute("INSERT INTO rotation (name) VALUES (?1)", params![name])?;
+        let rotation = Self::rotation(&tx)?;
+        let names = Self::names(&tx)?;
+        let full = !names.is_empty() && names.is_subset(&rotation);
+        if full {
+            tx.execute("DELETE FROM rotation", [])?;
+        }
+        tx.commit()?;
+        Ok(full)
+    }
+
+    async fn reset_reviewers(&self) -> Result<()> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        Self::fill_reviewers(&tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn get_random_name(&self) -> Result<String> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        let mut remaining = Self::reviewers(&tx)?;
+        if remaining.is_empty() {
+            Self::fill_reviewers(&tx)?;
+            remaining = Self::reviewers(&tx)?;
+        }
+        let person = remaining
+            .choose(&mut rand::thread_rng())
+            .ok_or_else(|| anyhow!("No reviewers left to pick from"))?
+            .to_owned();
+        tx.execute(
+            "UPDATE reviewers SET reviewed_on = ?1 WHERE name = ?2",
+            params![today(), person],
+        )?;
+        tx.commit()?;
+        Ok(person)
+    }
+
+    async fn get_current(&self) -> Result<Album> {
+        let conn = self.conn.lock().await;
+        Self::current(&conn)
+    }
+
+    async fn mark_played(&self, album: &Album) -> Result<()> {
+        let conn = self.conn.lock().await;
+        let updated = conn.execute(
+            "UPDATE albums SET played_on = ?1 WHERE id = ?2",
+            params![album.played_on.clone().unwrap_or_else(today), album.row],
+        )?;
+        if updated == 0 {
+            return Err(anyhow!("{} is no longer in the backlog", album));
+        }
+        Ok(())
+    }
+
+    async fn unmark_played(&self, album: &Album) -> Result<()> {
+        let conn = self.conn.lock().await;
+        let updated = conn.execute(
+            "UPDATE albums SET played_on = NULL WHERE id = ?1",
+            params![album.row],
+        )?;
+        if updated == 0 {
+            return Err(anyhow!("{} is no longer in the backlog", album));
+        }
+        Ok(())
+    }
+
+    async fn remove_current(&self, album: &Album) -> Result<()> {
+        let conn = self.conn.lock().await;
+        let current = Self::current(&conn)?;
+        if !same_album(&current, album) {
+            return Err(anyhow!("{} is no longer the current album", album));
+        }
+        conn.execute("DELETE FROM ratings WHERE id = ?1", params![current.row])?;
+        Ok(())
+    }
+
+    async fn list_albums(&self) -> Result<Vec<Album>> {
+        let conn = self.conn.lock().await;
+        Self::albums(&conn)
+    }
+
+    async fn list_members(&self) -> Result<Vec<Member>> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare("SELECT name, active, joined FROM members ORDER BY rowid")?;
+        let members = stmt
+            .query_map([], |row| {
+                Ok(Member {
+                    name: row.get(0)?,
+                    active: row.get(1)?,
+                    joined: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<Member>>>()?;
+        Ok(members)
+    }
+
+    async fn list_rotation(&self) -> Result<Vec<String>> {
+        let conn = self.conn.lock().await;
+        Self::strings(&conn, "SELECT name FROM rotation ORDER BY id")
+    }
+
+    async fn list_ratings(&self) -> Result<Vec<Rating>> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT id, artist, name, genre, added_by, year, length, date, extra
+            FROM ratings ORDER BY id DESC",
+        )?;
+        let ratings = stmt
+            .query_map([], |row| {
+                Ok((album_from_row(row)?, row.get(7)?, row.get::<_, String>(8)?))
+            })?
+            .map(|result| {
+                let (album, date, extra) = result?;
+                Ok(Rating {
+                    album,
+                    date,
+                    extra: serde_json::from_str(&extra)?,
+                })
+            })
+            .collect::<Result<Vec<Rating>>>()?;
+        Ok(ratings)
+    }
+
+    async fn add_albums(&self, albums: &[Album]) -> Result<()> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        for album in albums {
+            tx.execute(
+                "INSERT INTO albums
+                (artist, name, genre, added_by, year, length, played_on, added_on, pitch,
+                spotify_id, submitter_id)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                params![
+                    album.artist,
+                    album.name,
+                    album.genre,
+                    album.added_by,
+                    album.year,
+                    album.length,
+                    album.played_on,
+                    album.added_on,
+                    album.pitch,
+                    album.spotify_id,
+                    album.submitter_id
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn update_album(&self, album: &Album, edited: &Album) -> Result<()> {
+        let conn = self.conn.lock().await;
+        let updated = conn.execute(
+            "UPDATE albums SET artist = ?1, name = ?2, genre = ?3, year = ?4, length = ?5,
+            pitch = ?6, spotify_id = ?7 WHERE id = ?8",
+            params![
+                edited.artist,
+                edited.name,
+                edited.genre,
+                edited.year,
+                edited.length,
+                edited.pitch,
+                edited.spotify_id,
+                album.row
+            ],
+        )?;
+        if updated == 0 {
+            return Err(anyhow!("{} is no longer in the backlog", album));
+        }
+        Ok(())
+    }
+
+    async fn remove_album(&self, album: &Album) -> Result<()> {
+        let conn = self.conn.lock().await;
+        let removed = conn.execute("DELETE FROM albums WHERE id = ?1", params![album.row])?;
+        if removed == 0 {
+            return Err(anyhow!("{} is no longer in the backlog", album));
+        }
+        Ok(())
+    }
+
+    async fn add_members(&self, members: &[Member]) -> Result<()> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        for member in members {
+            tx.execute(
+                "INSERT OR IGNORE INTO members (name, active, joined) VALUES (?1, ?2, ?3)",
+                params![member.name, member.active, member.joined],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn set_rotation(&self, names: &[String]) -> Result<()> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM rotation", [])?;
+        for name in names {
+            tx.execute("INSERT INTO rotation (name) VALUES (?1)", params![name])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn add_ratings(&self, ratings: &[Rating]) -> Result<()> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        // Oldest first, so the newest rating ends up with the highest id
+        for rating in ratings.iter().rev() {
+            let album = &rating.album;
+            tx.execute(
+                "INSERT INTO ratings (artist, name, genre, added_by, year, length, date, extra)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    album.artist,
+                    album.name,
+                    album.genre,
+                    album.added_by,
+                    album.year,
+                    album.length,
+                    rating.date,
+                    serde_json::to_string(&rating.extra)?
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn add_draw(&self, draw: &DrawRecord) -> Result<()> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "INSERT INTO draws (date, seed_hash, record) VALUES (?1, ?2, ?3)",
+            params![draw.date, draw.seed_hash, serde_json::to_string(draw)?],
+        )?;
+        Ok(())
+    }
+
+    async fn list_draws(&self) -> Result<Vec<DrawRecord>> {
+        let conn = self.conn.lock().await;
+        Self::strings(&conn, "SELECT record FROM draws ORDER BY id")?
+            .iter()
+            .map(|record| Ok(serde_json::from_str(record)?))
+            .collect()
+    }
+
+    async fn get_state(&self, key: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().await;
+        Ok(conn
+            .query_row(
+                "SELECT value FROM state WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    async fn set_state(&self, key: &str, value: Option<&str>) -> Result<()> {
+        let conn = self.conn.lock().await;
+        match value {
+            Some(value) => conn.execute(
+                "INSERT OR REPLACE INTO state (key, value) VALUES (?1, ?2)",
+                params![key, value],
+            )?,
+            None => conn.execute("DELETE FROM state WHERE key = ?1", params![key])?,
+        };
+        Ok(())
+    }
+
+    fn locate(&self, album: &Album) -> String {
+        format!("id {} in the albums table", album.row)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::albums::Submitter;
+    use crate::draw::new_seed;
+    use crate::selection::RuleSet;
+
+    async fn seeded_repo() -> Result<SqliteAlbumRepo> {
+        let repo = SqliteAlbumRepo::open(":memory:")?;
+        let conn = repo.conn.lock().await;
+        conn.execute_batch(
+            "INSERT INTO members (name) VALUES ('Ann'), ('Bob'), ('Cat');
+            INSERT INTO albums (artist, name, genre, added_by) VALUES
+                ('Aphex Twin', 'Syro', 'Electronic', 'Ann'),
+                ('Slowdive', 'Souvlaki', 'Shoegaze', 'Bob'),
+                ('Burial', 'Untrue', 'Electronic', 'Cat');
+            INSERT INTO ratings (artist, name, genre, added_by) VALUES
+                ('Low', 'Things We Lost in the Fire', 'Slowcore', 'Cat');",
+        )?;
+        drop(conn);
+        Ok(repo)
+    }
+
+    #[tokio::test]
+    async fn test_migrations_are_idempotent() -> Result<()> {
+        let mut conn = Connection::open_in_memory()?;
+        migrate(&mut conn)?;
+        migrate(&mut conn)?;
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        assert_eq!(version, MIGRATIONS.len());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_submissions_are_checked() -> Result<()> {
+        let repo = seeded_repo().await?;
+        let submission = Album {
+            artist: " Grouper ".to_owned(),
+            name: "Dragging a Dead Deer Up a Hill".to_owned(),
+            genre: "Ambient".to_owned(),
+            added_by: "Ann".to_owned(),
+            pitch: Some("Fog, in album form".to_owned()),
+            ..Default::default()
+        };
+        let added = repo.submit_album(&submission).await?;
+        assert_eq!(added.artist, "Grouper");
+        assert_eq!(added.added_on, Some(today()));
+        let stored = repo.list_albums().await?.pop().unwrap();
+        assert_eq!(stored.pitch, submission.pitch);
+
+        let no_genre = Album {
+            genre: " ".to_owned(),
+            ..submission.clone()
+        };
+        let err = repo.submit_album(&no_genre).await.unwrap_err();
+        assert_eq!(err.to_string(), "Unable to get album genre");
+        let again = Album {
+            artist: "grouper".to_owned(),
+            name: "Dragging a Dead Deer Up a Hill!".to_owned(),
+            added_by: "Bob".to_owned(),
+            ..submission
+        };
+        let err = repo.submit_album(&again).await.unwrap_err().to_string();
+        assert!(
+            err.contains("is already in the backlog (id 4 in the albums table), submitted by Ann"),
+            "{}",
+            err
+        );
+        let played = Album {
+            artist: "Low".to_owned(),
+            name: "Things We Lost In The Fire".to_owned(),
+            ..again
+        };
+        let err = repo.submit_album(&played).await.unwrap_err().to_string();
+        assert!(err.contains("has already been played"), "{}", err);
+        assert_eq!(repo.list_albums().await?.len(), 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_submissions_are_managed_by_their_submitter() -> Result<()> {
+        let repo = seeded_repo().await?;
+        let submission = Album {
+            artist: "Grouper".to_owned(),
+            name: "Ruins".to_owned(),
+            genre: "Ambient".to_owned(),
+            added_by: "Ann".to_owned(),
+            submitter_id: Some("1".to_owned()),
+            ..Default::default()
+        };
+        repo.submit_album(&submission).await?;
+        let ann = Submitter::Id("1".to_owned());
+        let mine = repo.list_submissions(&ann).await?;
+        assert_eq!(mine.len(), 1);
+        let album = mine[0].clone();
+        // Syro was added by hand, so it can only be changed by name
+        let by_name = repo
+            .list_submissions(&Submitter::Name("Ann".to_owned()))
+            .await?;
+        assert_eq!(by_name.len(), 2);
+
+        let edited = Album {
+            genre: "Drone".to_owned(),
+            pitch: Some("Worth it".to_owned()),
+            ..album.clone()
+        };
+        // Someone calling themselves Ann still isn't her
+        let impostor = Submitter::Id("2".to_owned());
+        assert!(repo
+            .edit_submission(&impostor, album.row, &edited)
+            .await
+            .is_err());
+        let saved = repo.edit_submission(&ann, album.row, &edited).await?;
+        assert_eq!(saved.genre, "Drone");
+        assert_eq!(repo.list_submissions(&ann).await?[0].pitch, edited.pitch);
+
+        assert!(repo
+            .withdraw_submission(&impostor, album.row)
+            .await
+            .is_err());
+        repo.withdraw_submission(&ann, album.row).await?;
+        assert!(repo.list_submissions(&ann).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_random_album_follows_rules() -> Result<()> {
+        let repo = seeded_repo().await?;
+        repo.add_name_to_rotation("Bob".to_owned()).await?;
+        // Bob is in the rotation and Cat submitted the current album, so only Ann's is left
+        let pick = repo
+            .fetch_random_album(&RuleSet::default(), &new_seed())
+            .await?;
+        assert_eq!(pick.album.name, "Syro");
+        assert!(pick.relaxed.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_played_albums_are_not_drawn() -> Result<()> {
+        let repo = seeded_repo().await?;
+        let syro = repo.list_albums().await?.remove(0);
+        repo.mark_played(&syro).await?;
+        // Cat submitted the current album, so Souvlaki is all that's left
+        for _ in 0..5 {
+            assert_eq!(
+                repo.fetch_random_album(&RuleSet::default(), &new_seed())
+                    .await?
+                    .album
+                    .name,
+                "Souvlaki"
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_set_current_feeds_the_next_draw() -> Result<()> {
+        let repo = seeded_repo().await?;
+        let syro = repo.list_albums().await?.remove(0);
+        repo.set_current(&syro).await?;
+        assert_eq!(repo.get_current().await?.name, "Syro");
+        assert_eq!(repo.list_ratings().await?[0].date, Some(today()));
+        // Ann and Electronic are now on cooldown, which leaves Souvlaki
+        for _ in 0..5 {
+            assert_eq!(
+                repo.fetch_random_album(&RuleSet::default(), &new_seed())
+                    .await?
+                    .album
+                    .name,
+                "Souvlaki"
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_draws_are_kept() -> Result<()> {
+        let repo = seeded_repo().await?;
+        let pick = repo
+            .fetch_random_album(&RuleSet::default(), &new_seed())
+            .await?;
+        repo.add_draw(&pick.draw).await?;
+        let draws = repo.list_draws().await?;
+        assert_eq!(draws, vec![pick.draw]);
+        draws[0].verify()?;
+        assert_eq!(draws[0].pick()?.album, pick.album.name);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_commits_can_be_taken_back() -> Result<()> {
+        let repo = seeded_repo().await?;
+        let syro = repo.list_albums().await?.remove(0);
+        repo.mark_played(&syro).await?;
+        repo.set_current(&syro).await?;
+        let untrue = repo.list_albums().await?.remove(2);
+        assert!(repo.remove_current(&untrue).await.is_err());
+
+        repo.remove_current(&syro).await?;
+        repo.unmark_played(&syro).await?;
+        assert_eq!(repo.get_current().await?.name, "Things We Lost in the Fire");
+        assert_eq!(repo.list_albums().await?[0].played_on, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_state_is_kept() -> Result<()> {
+        let repo = seeded_repo().await?;
+        assert_eq!(repo.get_state("pending_pick").await?, None);
+        repo.set_state("pending_pick", Some("one")).await?;
+        repo.set_state("pending_pick", Some("two")).await?;
+        assert_eq!(
+            repo.get_state("pending_pick").await?.as_deref(),
+            Some("two")
+        );
+        repo.set_state("pending_pick", None).await?;
+        assert_eq!(repo.get_state("pending_pick").await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_full_rotation_is_cleared() -> Result<()> {
+        let repo = seeded_repo().await?;
+        assert!(!repo.add_name_to_rotation("Ann".to_owned()).await?);
+        assert!(!repo.add_name_to_rotation("Bob".to_owned()).await?);
+        assert!(repo.add_name_to_rotation("Cat".to_owned()).await?);
+        let conn = repo.conn.lock().await;
+        assert!(SqliteAlbumRepo::rotation(&conn)?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_inactive_members_are_left_out() -> Result<()> {
+        let repo = seeded_repo().await?;
+        repo.add_members(&[Member {
+            name: "Dan".to_owned(),
+            active: false,
+            joined: None,
+        }])
+        .await?;
+        // Dan never submits, but being inactive he doesn't hold the rotation open
+        for name in ["Ann", "Bob", "Cat"] {
+            repo.add_name_to_rotation(name.to_owned()).await?;
+        }
+        assert!(repo.list_rotation().await?.is_empty());
+        assert_eq!(repo.list_members().await?.len(), 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reviewers_skip_current_submitter() -> Result<()> {
+        let repo = seeded_repo().await?;
+        let mut reviewers = vec![repo.get_random_name().await?, repo.get_random_name().await?];
+        reviewers.sort();
+        assert_eq!(reviewers, vec!["Ann", "Bob"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reviewer_cycle_survives_a_restart() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("reviewers-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let repo = SqliteAlbumRepo::open(path)?;
+        repo.add_members(&[
+            Member {
+                name: "Ann".to_owned(),
+                active: true,
+                joined: None,
+            },
+            Member {
+                name: "Bob".to_owned(),
+                active: true,
+                joined: None,
+            },
+        ])
+        .await?;
+        repo.set_current(&Album {
+            name: "Trust".to_owned(),
+            added_by: "Cat".to_owned(),
+            ..Default::default()
+        })
+        .await?;
+        let first = repo.get_random_name().await?;
+        drop(repo);
+
+        let repo = SqliteAlbumRepo::open(path)?;
+        let second = repo.get_random_name().await?;
+        std::fs::remove_file(path)?;
+        assert_ne!(first, second);
+        Ok(())
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+use crate::albums::{Album, AlbumRepo, Member, Rating};
+use crate::layout::{column_index, SheetLayout};
+
+use anyhow::{anyhow, Result};
+
+/// The raw cell values of each tab in the club spreadsheet, keyed by tab name.
+/// This is the on-disk format for `dump` and for `dump:<path>` sync sources.
+pub type SheetDump = BTreeMap<String, Vec<Vec<String>>>;
+
+/// Everything a backend knows about the club, read in one go.
+#[derive(Clone, Debug, Default)]
+pub struct ClubSnapshot {
+    pub albums: Vec<Album>,
+    pub members: Vec<Member>,
+    pub rotation: Vec<String>,
+    pub ratings: Vec<Rating>,
+}
+
+impl ClubSnapshot {
+    pub async fn from_repo(repo: &(dyn AlbumRepo + Send + Sync)) -> Result<Self> {
+        Ok(ClubSnapshot {
+            albums: repo.list_albums().await?,
+            members: repo.list_members().await?,
+            rotation: repo.list_rotation().await?,
+            ratings: repo.list_ratings().await?,
+        })
+    }
+
+    /// Reads a dump the same way `GoogleSheetsAlbumRepo` reads the live sheet,
+    /// including falling back to the names column without a members tab.
+    pub fn from_dump(dump: &SheetDump, layout: &SheetLayout) -> Result<Self> {
+        let tab = |name: &str| {
+            dump.get(name)
+                .map(Vec::as_slice)
+                .ok_or_else(|| anyhow!("Dump is missing the {} tab", name))
+        };
+        let column = |values: &[Vec<String>], letters: &str| -> Result<Vec<String>> {
+            let index = column_index(letters)?;
+            Ok(values
+                .iter()
+                .filter_map(|row| row.get(index))
+                .filter(|cell| !cell.is_empty())
+                .cloned()
+                .collect())
+        };
+        let rotation_tab = tab(&layout.rotation_tab)?;
+        let members = match dump.get(&layout.members_tab) {
+            Some(values) => match values.split_first() {
+                Some((header, rows)) => layout.member_columns(header)?.members(rows),
+                None => Vec::new(),
+            },
+            None => column(rotation_tab, &layout.names_column)?
+                .into_iter()
+                .map(|name| Member {
+                    name,
+                    active: true,
+                    joined: None,
+                })
+                .collect(),
+        };
+        let (album_columns, albums) =
+            layout.split_tab(&layout.albums_tab, tab(&layout.albums_tab)?)?;
+        let (rating_columns, ratings) =
+            layout.split_tab(&layout.ratings_tab, tab(&layout.ratings_tab)?)?;
+        Ok(ClubSnapshot {
+            albums: album_columns.albums(albums)?,
+            members,
+            rotation: column(rotation_tab, &layout.rotation_column)?,
+            ratings: rating_columns.ratings(ratings)?,
+        })
+    }
+
+    pub fn to_dump(&self, layout: &SheetLayout) -> Result<SheetDump> {
+        let albums_header = layout.albums_header();
+        let album_columns = layout.columns(&layout.albums_tab, &albums_header)?;
+        let mut albums = vec![albums_header];
+        albums.extend(
+            self.albums
+                .iter()
+                .map(|album| album_columns.backlog_row(album)),
+        );
+
+        let ratings_header = layout.ratings_header();
+        let rating_columns = layout.columns(&layout.ratings_tab, &ratings_header)?;
+        let mut ratings = vec![ratings_header];
+        ratings.extend(
+            self.ratings
+                .iter()
+                .map(|rating| rating_columns.rating_row(rating)),
+        );
+
+        let members_header = layout.members_header();
+        let member_columns = layout.member_columns(&members_header)?;
+        let mut members = vec![members_header];
+        members.extend(
+            self.members
+                .iter()
+                .map(|member| member_columns.member_row(member)),
+        );
+
+        let rotation_column = column_index(&layout.rotation_column)?;
+        let rotation = self
+            .rotation
+            .iter()
+            .map(|name| {
+                let mut row = vec![String::new(); rotation_column + 1];
+                row[rotation_column] = name.clone();
+                row
+            })
+            .collect();
+
+        let mut dump = SheetDump::new();
+        dump.insert(layout.albums_tab.clone(), albums);
+        dump.insert(layout.rotation_tab.clone(), rotation);
+        dump.insert(layout.members_tab.clone(), members);
+        dump.insert(layout.ratings_tab.clone(), ratings);
+        Ok(dump)
+    }
+}
+
+/// Two albums are the same entry if the artist, title and submitter match,
+/// ignoring case and surrounding whitespace.
+fn album_key(album: &Album) -> (String, String, String) {
+    (
+        album.artist.trim().to_lowercase(),
+        album.name.trim().to_lowercase(),
+        album.added_by.trim().to_lowercase(),
+    )
+}
+
+/// What `sync` changed, or would change on a dry run.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub albums_added: Vec<Album>,
+    pub albums_present: usize,
+    /// Albums both sides have that only the source has played.
+    pub albums_played: Vec<Album>,
+    pub members_added: Vec<Member>,
+    pub members_present: usize,
+    pub rotation_changed: Option<(Vec<String>, Vec<String>)>,
+    pub ratings_added: Vec<Rating>,
+    pub ratings_present: usize,
+    /// Ratings the destination lacks that are older than ones it already has.
+    /// Backends only grow the history from the top, so these are left alone.
+    pub ratings_skipped: Vec<Rating>,
+}
+
+impl SyncReport {
+    pub fn is_empty(&self) -> bool {
+        self.albums_added.is_empty()
+            && self.albums_played.is_empty()
+            && self.members_added.is_empty()
+            && self.rotation_changed.is_none()
+            && self.ratings_added.is_empty()
+            && self.ratings_skipped.is_empty()
+    }
+}
+
+impl Display for SyncReport {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Albums: {} added, {} already present",
+            self.albums_added.len(),
+            self.albums_present
+        )?;
+        for album in &self.albums_added {
+            writeln!(f, "  + {}", album)?;
+        }
+        for album in &self.albums_played {
+            writeln!(f, "  ~ {} marked as played", album)?;
+        }
+        writeln!(
+            f,
+            "Members: {} added, {} already present",
+            self.members_added.len(),
+            self.members_present
+        )?;
+        for member in &self.members_added {
+            writeln!(f, "  + {}", member.name)?;
+        }
+        match &self.rotation_changed {
+            Some((before, after)) => writeln!(
+                f,
+                "Rotation: [{}] -> [{}]",
+                before.join(", "),
+                after.join(", ")
+            )?,
+            None => writeln!(f, "Rotation: unchanged")?,
+        }
+        writeln!(
+            f,
+            "Ratings: {} added, {} already present, {} skipped",
+            self.ratings_added.len(),
+            self.ratings_present,
+            self.ratings_skipped.len()
+        )?;
+        for rating in &self.ratings_added {
+            writeln!(f, "  + {}", rating.album)?;
+        }
+        for rating in &self.ratings_skipped {
+            writeln!(
+                f,
+                "  ! {} is older than the destination's history, skipped",
+                rating.album
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Copies everything in `from` that `to` is missing. Running it twice is a no-op:
+/// albums, members and ratings are matched by content rather than position, and
+/// the rotation is only rewritten when it differs.
+pub async fn sync(
+    from: &ClubSnapshot,
+    to: &(dyn AlbumRepo + Send + Sync),
+    dry_run: bool,
+) -> Result<SyncReport> {
+    let existing = ClubSnapshot::from_repo(to).await?;
+    let mut report = SyncReport::default();
+
+    let mut albums: HashMap<_, &Album> = existing
+        .albums
+        .iter()
+        .map(|album| (album_key(album), album))
+        .collect();
+    for album in &from.albums {
+        match albums.get(&album_key(album)) {
+            Some(other) => {
+                report.albums_present += 1;
+                if other.played_on.is_none() && album.played_on.is_some() {
+                    report.albums_played.push(Album {
+                        row: other.row,
+                        played_on: album.played_on.clone(),
+                        ..album.clone()
+                    });
+                }
+            }
+            None => {
+                albums.insert(album_key(album), album);
+                report.albums_added.push(album.clone());
+            }
+        }
+    }
+
+    let mut names: HashSet<&String> = existing.members.iter().map(|m| &m.name).collect();
+    for member in &from.members {
+        if names.insert(&member.name) {
+            report.members_added.push(member.clone());
+        } else {
+            report.members_present += 1;
+        }
+    }
+
+    let rotation_before: HashSet<&String> = existing.rotation.iter().collect();
+    let rotation_after: HashSet<&String> = from.rotation.iter().collect();
+    if rotation_before != rotation_after {
+        report.rotation_changed = Some((existing.rotation.clone(), from.rotation.clone()));
+    }
+
+    let rating_keys: HashSet<_> = existing
+        .ratings
+        .iter()
+        .map(|rating| album_key(&rating.album))
+        .collect();
+    // Only the ratings newer than everything the destination already has can go on top
+    let mut on_top = true;
+    for rating in &from.ratings {
+        if rating_keys.contains(&album_key(&rating.album)) {
+            report.ratings_present += 1;
+            on_top = false;
+        } else if on_top {
+            report.ratings_added.push(rating.clone());
+        } else {
+            report.ratings_skipped.push(rating.clone());
+        }
+    }
+
+    if !dry_run {
+        if !report.albums_added.is_empty() {
+            to.add_albums(&report.albums_added).await?;
+        }
+        for album in &report.albums_played {
+            to.mark_played(album).await?;
+        }
+        if !report.members_added.is_empty() {
+            to.add_members(&report.members_added).await?;
+        }
+        if report.rotation_changed.is_some() {
+            to.set_rotation(&from.rotation).await?;
+        }
+        if !report.ratings_added.is_empty() {
+            to.add_ratings(&report.ratings_added).await?;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sqlite::SqliteAlbumRepo;
+
+    fn fixture() -> Result<ClubSnapshot> {
+        let dump: SheetDump = serde_json::from_str(include_str!("../fixtures/sheet_dump.json"))?;
+        ClubSnapshot::from_dump(&dump, &SheetLayout::default())
+    }
+
+    #[test]
+    fn test_reading_a_dump() -> Result<()> {
+        let snapshot = fixture()?;
+        assert_eq!(snapshot.albums.len(), 4);
+        assert_eq!(snapshot.albums[3].played_on.as_deref(), Some("2021-11-02"));
+        let names: Vec<&str> = snapshot.members.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["Ann", "Bob", "Cat"]);
+        assert_eq!(snapshot.rotation, vec!["Bob"]);
+        assert_eq!(snapshot.ratings[0].album.name, "Things We Lost in the Fire");
+        assert_eq!(snapshot.ratings[0].extra, vec!["8", "7", "9"]);
+        assert_eq!(snapshot.ratings[0].date.as_deref(), Some("2022-05-20"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump_round_trip() -> Result<()> {
+        let snapshot = fixture()?;
+        let layout = SheetLayout::default();
+        let again = ClubSnapshot::from_dump(&snapshot.to_dump(&layout)?, &layout)?;
+        assert_eq!(again.albums.len(), snapshot.albums.len());
+        assert_eq!(again.members, snapshot.members);
+        assert_eq!(again.rotation, snapshot.rotation);
+        assert_eq!(again.ratings.len(), snapshot.ratings.len());
+        assert_eq!(again.ratings[0].extra, snapshot.ratings[0].extra);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_is_idempotent() -> Result<()> {
+        let snapshot = fixture()?;
+        let repo = SqliteAlbumRepo::open(":memory:")?;
+
+        let report = sync(&snapshot, &repo, false).await?;
+        assert_eq!(report.albums_added.len(), 4);
+        assert_eq!(report.ratings_added.len(), 2);
+        assert!(report.rotation_changed.is_some());
+        assert_eq!(repo.get_current().await?.name, "Things We Lost in the Fire");
+
+        let report = sync(&snapshot, &repo, false).await?;
+        assert!(report.is_empty(), "{}", report);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_played_albums_are_carried_over() -> Result<()> {
+        let mut snapshot = fixture()?;
+        let repo = SqliteAlbumRepo::open(":memory:")?;
+        sync(&snapshot, &repo, false).await?;
+
+        snapshot.albums[1].played_on = Some("2022-06-01".to_owned());
+        let report = sync(&snapshot, &repo, false).await?;
+        assert_eq!(report.albums_played.len(), 1);
+        let albums = repo.list_albums().await?;
+        assert_eq!(albums[1].played_on.as_deref(), Some("2022-06-01"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_changes_nothing() -> Result<()> {
+        let snapshot = fixture()?;
+        let repo = SqliteAlbumRepo::open(":memory:")?;
+        let report = sync(&snapshot, &repo, true).await?;
+        assert!(!report.is_empty());
+        assert!(repo.list_albums().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_newer_ratings_go_on_top() -> Result<()> {
+        let mut snapshot = fixture()?;
+        let repo = SqliteAlbumRepo::open(":memory:")?;
+        sync(&snapshot, &repo, false).await?;
+
+        let mut newest = snapshot.ratings[0].clone();
+        newest.album.name = "Spiderland".to_owned();
+        newest.album.artist = "Slint".to_owned();
+        snapshot.ratings.insert(0, newest);
+        let report = sync(&snapshot, &repo, false).await?;
+        assert_eq!(report.ratings_added.len(), 1);
+        assert_eq!(repo.get_current().await?.name, "Spiderland");
+        Ok(())
+    }
+}